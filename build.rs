@@ -0,0 +1,38 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// The Makefile builds the kernel twice. The first build has no symbols, and
+// its functions are extracted into a table with `symtab`. The second build
+// embeds that table, which is placed after the code so that none of the
+// functions move. The kernel finds the table through symbols that link.ld
+// defines around it, rather than through its length, so that the code is
+// compiled the same way whether or not the table is empty.
+//
+// It also passes the public key that updates have to be signed with, if
+// there is one.
+fn main() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+
+    let table = read_optional("KERNEL_SYMBOLS");
+    fs::write(out_dir.join("symbols.bin"), table).unwrap();
+
+    let public_key = read_optional("KERNEL_PUBLIC_KEY");
+    if !public_key.is_empty() && public_key.len() != 32 {
+        panic!("KERNEL_PUBLIC_KEY should be a 32 byte Ed25519 public key, but it's {} bytes", public_key.len());
+    }
+    fs::write(out_dir.join("public_key.bin"), public_key).unwrap();
+}
+
+/// Read the file named by the environment variable `var`, or return nothing if
+/// it isn't set
+fn read_optional(var: &str) -> Vec<u8> {
+    println!("cargo:rerun-if-env-changed={}", var);
+    match env::var(var) {
+        Ok(path) if !path.is_empty() => {
+            println!("cargo:rerun-if-changed={}", path);
+            fs::read(Path::new(&path)).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e))
+        },
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,37 @@
+use crate::symbols::Located;
+use core::fmt;
+use utils::backtrace::{Frames, StackBounds};
+
+extern "C" {
+    static __program_end: usize;
+    fn get_stack_ptr() -> usize;
+}
+
+/// The bounds of the boot stack, which `get_stack_ptr` places just above the
+/// end of the program
+#[cfg(target_arch = "aarch64")]
+pub fn stack_bounds() -> StackBounds {
+    unsafe {
+        StackBounds {
+            low: &__program_end as *const usize as usize,
+            high: get_stack_ptr(),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+pub fn stack_bounds() -> StackBounds {
+    StackBounds { low: 0, high: 0 }
+}
+
+/// Print the return addresses in the chain of frame records starting at
+/// `frame_pointer`
+pub fn print(out: &mut dyn fmt::Write, frame_pointer: usize) -> fmt::Result {
+    writeln!(out, "Backtrace:")?;
+    // Safe because the stack is always mapped
+    let frames = unsafe { Frames::new(frame_pointer, stack_bounds()) };
+    for (i, address) in frames.enumerate() {
+        writeln!(out, "  #{:<2} {}", i, Located(address as u64))?;
+    }
+    Ok(())
+}
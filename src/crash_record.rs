@@ -0,0 +1,149 @@
+//! Keeps the panic output from the last boot, so that the reason for a crash
+//! isn't lost when there's nothing attached to the serial port.
+//!
+//! The record lives in a fixed region of RAM just below the kernel, which
+//! `init::reset` doesn't zero and a watchdog reboot doesn't clear. The region
+//! is at the same address in every build, so a record survives updating the
+//! kernel too.
+
+use core::fmt;
+use core::ptr;
+use utils::crc::Crc32;
+use utils::sync::Mutex;
+
+/// "PANC", which marks a record as having been written
+const MAGIC: u32 = 0x5041_4E43;
+/// The space for the panic output. The whole record has to fit in the region
+/// that link.ld reserves.
+const MAX_TEXT_LENGTH: usize = 2048 - 12;
+
+#[repr(C)]
+struct Record {
+    magic: u32,
+    len: u32,
+    /// The CRC-32 of the length and the text
+    crc: u32,
+    text: [u8; MAX_TEXT_LENGTH],
+}
+
+extern "C" {
+    // The address of the record, which is defined in link.ld
+    static __crash_record: u8;
+}
+
+/// The output from the panic that ended the last boot
+pub struct PreviousCrash {
+    text: [u8; MAX_TEXT_LENGTH],
+    len: usize,
+    /// Whether a record was found, and so whether the rest is meaningful
+    found: bool,
+}
+
+impl PreviousCrash {
+    pub fn text(&self) -> &str {
+        // Only whole characters were recorded, but the text could still have
+        // been corrupted in a way that the CRC didn't catch
+        match core::str::from_utf8(&self.text[..self.len]) {
+            Ok(text) => text,
+            Err(e) => core::str::from_utf8(&self.text[..e.valid_up_to()]).unwrap_or(""),
+        }
+    }
+
+    /// The first line, which says why it panicked
+    pub fn summary(&self) -> &str {
+        self.text().lines().next().unwrap_or("")
+    }
+}
+
+// This is filled in place by `check`, because it's too big to build on the
+// stack
+static PREVIOUS: Mutex<PreviousCrash> = Mutex::new(PreviousCrash {
+    text: [0; MAX_TEXT_LENGTH],
+    len: 0,
+    found: false,
+});
+
+fn record() -> *mut Record {
+    unsafe { &__crash_record as *const u8 as usize as *mut Record }
+}
+
+fn checksum(len: u32, text: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(&len.to_le_bytes());
+    crc.update(text);
+    crc.finish()
+}
+
+/// Look for a record from the last boot, and keep it so that it can be shown
+/// later. The record is cleared, so the crash is only reported once. Returns
+/// whether there was a crash.
+pub fn check() -> bool {
+    let record = record();
+    unsafe {
+        let magic = ptr::read_volatile(&(*record).magic);
+        let len = ptr::read_volatile(&(*record).len);
+        ptr::write_volatile(&mut (*record).magic, 0);
+        if magic != MAGIC || len as usize > MAX_TEXT_LENGTH {
+            return false;
+        }
+
+        let mut previous = PREVIOUS.lock();
+        previous.len = len as usize;
+        ptr::copy_nonoverlapping((*record).text.as_ptr(), previous.text.as_mut_ptr(), previous.len);
+        previous.found = checksum(len, &previous.text[..previous.len]) == ptr::read_volatile(&(*record).crc);
+        previous.found
+    }
+}
+
+/// Call `f` with the crash from the last boot, if there was one
+pub fn with_previous<R>(f: impl FnOnce(Option<&PreviousCrash>) -> R) -> R {
+    let previous = PREVIOUS.lock();
+    f(if previous.found { Some(&previous) } else { None })
+}
+
+/// Writes panic output into the record. The record is only marked as valid
+/// when `finish` is called, so a panic part way through writing it doesn't
+/// leave a broken record behind.
+pub struct Recorder {
+    len: usize,
+}
+
+impl Recorder {
+    /// Start a new record, replacing any old one
+    pub fn start() -> Recorder {
+        unsafe {
+            ptr::write_volatile(&mut (*record()).magic, 0);
+        }
+        Recorder {
+            len: 0,
+        }
+    }
+
+    /// Mark the record as complete, so that it's found on the next boot
+    pub fn finish(self) {
+        let record = record();
+        unsafe {
+            let text = core::slice::from_raw_parts((*record).text.as_ptr(), self.len);
+            let crc = checksum(self.len as u32, text);
+            ptr::write_volatile(&mut (*record).len, self.len as u32);
+            ptr::write_volatile(&mut (*record).crc, crc);
+            ptr::write_volatile(&mut (*record).magic, MAGIC);
+        }
+    }
+}
+
+impl fmt::Write for Recorder {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Truncate at a character boundary once it's full
+        let mut len = s.len().min(MAX_TEXT_LENGTH - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        unsafe {
+            let text = (*record()).text.as_mut_ptr().add(self.len);
+            ptr::copy_nonoverlapping(s.as_ptr(), text, len);
+        }
+        self.len += len;
+        Ok(())
+    }
+}
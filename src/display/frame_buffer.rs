@@ -1,6 +1,7 @@
 use crate::peripherals::mailbox;
 use core::sync::atomic::{fence, Ordering};
 use core::convert::TryInto;
+use core::ptr;
 
 const MAILBOX_BUFFER_SIZE: usize = 42;
 
@@ -99,6 +100,10 @@ pub struct FrameBuffer {
     height: usize,
 }
 
+// The frame buffer memory belongs to whoever holds the FrameBuffer, so it's
+// safe to hand it to another core
+unsafe impl Send for FrameBuffer { }
+
 impl FrameBuffer {
     #[allow(clippy::identity_op)]
     pub fn new(width: u32, height: u32) -> Result<FrameBuffer, FrameBufferCreationError> {
@@ -206,6 +211,35 @@ impl FrameBuffer {
         fence(Ordering::Release);
     }
 
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Fill a rectangle with a single colour
+    #[allow(clippy::too_many_arguments)]
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, r: u8, g: u8, b: u8, a: u8) {
+        for y in y..y + height {
+            for x in x..x + width {
+                self.set_pixel(x, y, r, g, b, a);
+            }
+        }
+    }
+
+    /// Move everything up by `rows` rows of pixels, and fill the rows that
+    /// are uncovered at the bottom with the given colour
+    pub fn scroll_up(&mut self, rows: usize, r: u8, g: u8, b: u8, a: u8) {
+        let rows = rows.min(self.height);
+        let row_len = self.pitch / 4;
+        unsafe {
+            ptr::copy(self.buffer.add(rows * row_len), self.buffer, (self.height - rows) * row_len);
+        }
+        self.fill_rect(0, self.height - rows, self.width, rows, r, g, b, a);
+    }
+
     #[inline]
     pub fn set_pixel(&mut self, x: usize, y: usize, r: u8, g: u8, b: u8, a: u8) {
         assert!(x < self.width && y < self.height);
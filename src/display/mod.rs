@@ -1 +1,3 @@
-pub mod frame_buffer;
\ No newline at end of file
+pub mod font;
+pub mod frame_buffer;
+pub mod text_console;
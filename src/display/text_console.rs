@@ -0,0 +1,177 @@
+use super::font;
+use super::frame_buffer::{FrameBuffer, FrameBufferCreationError};
+use crate::io::Sink;
+use utils::sync::Mutex;
+
+/// Each pixel of the font is drawn as a square of this many pixels
+const SCALE: usize = 2;
+const CELL_WIDTH: usize = font::GLYPH_WIDTH * SCALE;
+const CELL_HEIGHT: usize = font::GLYPH_HEIGHT * SCALE;
+const TAB_WIDTH: usize = 8;
+
+#[derive(Clone, Copy)]
+struct Colour {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+const FOREGROUND: Colour = Colour { r: 0xCC, g: 0xCC, b: 0xCC };
+const BACKGROUND: Colour = Colour { r: 0, g: 0, b: 0 };
+
+/// Where we are in parsing an escape sequence. Only the sequences that the
+/// line editor uses are understood, and anything else is ignored.
+#[derive(Clone, Copy)]
+enum EscapeState {
+    None,
+    Escape,
+    Csi(usize),
+}
+
+/// Shows text on the frame buffer, so that console output can be seen on a
+/// monitor
+pub struct TextConsole {
+    screen: Mutex<Option<Screen>>,
+}
+
+struct Screen {
+    frame_buffer: FrameBuffer,
+    columns: usize,
+    rows: usize,
+    column: usize,
+    row: usize,
+    escape: EscapeState,
+}
+
+static TEXT_CONSOLE: TextConsole = TextConsole {
+    screen: Mutex::new(None),
+};
+
+pub fn get_text_console() -> &'static TextConsole {
+    &TEXT_CONSOLE
+}
+
+impl TextConsole {
+    /// Allocate a frame buffer of the given size, and start showing text on
+    /// it from the top left corner. This can be called again to change the
+    /// size, and if that fails the text console is left without a screen.
+    pub fn init(&self, width: u32, height: u32) -> Result<(), FrameBufferCreationError> {
+        // Allocating a new frame buffer can move the old one, so nothing can
+        // be allowed to draw on the old one in the meantime
+        let mut screen = self.screen.lock();
+        *screen = None;
+
+        let mut frame_buffer = FrameBuffer::new(width, height)?;
+        let (width, height) = (frame_buffer.width(), frame_buffer.height());
+        frame_buffer.fill_rect(0, 0, width, height, BACKGROUND.r, BACKGROUND.g, BACKGROUND.b, 255);
+
+        *screen = Some(Screen {
+            frame_buffer,
+            columns: width / CELL_WIDTH,
+            rows: height / CELL_HEIGHT,
+            column: 0,
+            row: 0,
+            escape: EscapeState::None,
+        });
+        Ok(())
+    }
+
+    /// Run `f` on the frame buffer that the text console is drawing on, if
+    /// it has one. Text is drawn over whatever `f` leaves behind.
+    pub fn with_frame_buffer<R, F: FnOnce(&mut FrameBuffer) -> R>(&self, f: F) -> Option<R> {
+        self.screen.lock().value_mut().as_mut().map(|screen| f(&mut screen.frame_buffer))
+    }
+}
+
+impl Sink for TextConsole {
+    fn write_str(&self, s: &str) {
+        if let Some(screen) = self.screen.lock().value_mut() {
+            for c in s.chars() {
+                screen.put(c);
+            }
+        }
+    }
+}
+
+impl Screen {
+    fn put(&mut self, c: char) {
+        match self.escape {
+            EscapeState::None => (),
+            EscapeState::Escape => {
+                self.escape = if c == '[' { EscapeState::Csi(0) } else { EscapeState::None };
+                return;
+            },
+            EscapeState::Csi(param) => {
+                if let Some(digit) = c.to_digit(10) {
+                    self.escape = EscapeState::Csi(param.saturating_mul(10).saturating_add(digit as usize));
+                    return;
+                }
+                self.escape = EscapeState::None;
+                match c {
+                    'C' => self.column = (self.column + param.max(1)).min(self.columns.saturating_sub(1)),
+                    'D' => self.column = self.column.saturating_sub(param.max(1)),
+                    'K' => self.clear(self.column, self.columns),
+                    _ => (),
+                }
+                return;
+            },
+        }
+
+        match c {
+            '\n' => self.new_line(),
+            '\r' => self.column = 0,
+            '\x08' => self.column = self.column.saturating_sub(1),
+            '\t' => {
+                self.column = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                if self.column >= self.columns {
+                    self.new_line();
+                }
+            },
+            '\x1b' => self.escape = EscapeState::Escape,
+            c if c.is_control() => (),
+            c => {
+                self.draw(font::glyph(c).or_else(|| font::glyph('?')));
+                self.column += 1;
+                if self.column == self.columns {
+                    self.new_line();
+                }
+            },
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.frame_buffer.scroll_up(CELL_HEIGHT, BACKGROUND.r, BACKGROUND.g, BACKGROUND.b, 255);
+        }
+    }
+
+    /// Clear the cells from `start` up to `end` in the current row
+    fn clear(&mut self, start: usize, end: usize) {
+        self.frame_buffer.fill_rect(
+            start * CELL_WIDTH, self.row * CELL_HEIGHT, (end - start) * CELL_WIDTH, CELL_HEIGHT,
+            BACKGROUND.r, BACKGROUND.g, BACKGROUND.b, 255
+        );
+    }
+
+    /// Draw a glyph in the cell at the cursor
+    fn draw(&mut self, glyph: Option<&[u8; 8]>) {
+        let glyph = match glyph {
+            Some(glyph) => glyph,
+            None => return,
+        };
+        let x = self.column * CELL_WIDTH;
+        let y = self.row * CELL_HEIGHT;
+        for (glyph_y, bits) in glyph.iter().enumerate() {
+            for glyph_x in 0..font::GLYPH_WIDTH {
+                let colour = if bits & (1 << glyph_x) != 0 { FOREGROUND } else { BACKGROUND };
+                self.frame_buffer.fill_rect(
+                    x + glyph_x * SCALE, y + glyph_y * SCALE, SCALE, SCALE,
+                    colour.r, colour.g, colour.b, 255
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use crate::gdb;
+use crate::io::emergency;
+use crate::symbols::Located;
+use core::fmt;
+
+/// The registers of the code that was running when an exception was taken.
+/// The layout has to match `exception_entry` in exceptions.S.
+#[repr(C)]
+pub struct TrapFrame {
+    /// x0 to x30
+    pub x: [u64; 31],
+    /// The address to return to
+    pub elr: u64,
+    pub spsr: u64,
+    /// The syndrome, which says why a synchronous exception was taken
+    pub esr: u64,
+    /// The faulting address, for aborts
+    pub far: u64,
+    _padding: u64,
+    /// The frame record that links the handler's backtrace to the
+    /// interrupted code
+    _frame_record: [u64; 2],
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "ESR: {:#010x} ({})", self.esr, exception_class_name(self.esr))?;
+        writeln!(f, "ELR: {}", Located(self.elr))?;
+        writeln!(f, "FAR: {:#018x} SPSR: {:#010x}", self.far, self.spsr)?;
+        for (i, pair) in self.x.chunks(2).enumerate() {
+            match pair {
+                [a, b] => writeln!(f, "x{:<2} {:#018x} x{:<2} {:#018x}", i * 2, a, i * 2 + 1, b)?,
+                [a] => writeln!(f, "x{:<2} {:#018x}", i * 2, a)?,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExceptionType {
+    Synchronous,
+    Irq,
+    Fiq,
+    SError,
+}
+
+/// Where the exception was taken from, which determines which group of
+/// vectors handles it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExceptionSource {
+    CurrentElSp0,
+    CurrentElSpx,
+    LowerElAArch64,
+    LowerElAArch32,
+}
+
+/// A short description of the exception class in ESR_EL1
+fn exception_class_name(esr: u64) -> &'static str {
+    match (esr >> 26) & 0x3F {
+        0x00 => "unknown reason",
+        0x01 => "trapped WFI or WFE",
+        0x07 => "trapped FP or SIMD access",
+        0x0E => "illegal execution state",
+        0x15 => "SVC",
+        0x18 => "trapped system register access",
+        0x20 | 0x21 => "instruction abort",
+        0x22 => "PC alignment fault",
+        0x24 | 0x25 => "data abort",
+        0x26 => "SP alignment fault",
+        0x2F => "SError",
+        0x30 | 0x31 => "breakpoint",
+        0x32 | 0x33 => "software step",
+        0x34 | 0x35 => "watchpoint",
+        0x3C => "BRK instruction",
+        _ => "other",
+    }
+}
+
+/// Install the exception vectors, so that exceptions are reported instead of
+/// jumping to whatever happens to be at VBAR_EL1
+#[cfg(target_arch = "aarch64")]
+pub fn init() {
+    extern "C" {
+        static exception_vectors: u8;
+    }
+    unsafe {
+        let vectors = &exception_vectors as *const u8 as u64;
+        asm!("MSR     VBAR_EL1, $0
+              ISB" :: "r"(vectors) : "memory" : "volatile");
+    }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+pub fn init() {
+}
+
+/// Called by `exception_entry` for every exception. `kind` is the index of the
+/// vector that was taken.
+#[no_mangle]
+extern "C" fn handle_exception(kind: u64, frame: &mut TrapFrame) {
+    // Another core may be panicking, in which case this one should stop
+    emergency::stop_if_not_owner();
+
+    let exception_type = match kind % 4 {
+        0 => ExceptionType::Synchronous,
+        1 => ExceptionType::Irq,
+        2 => ExceptionType::Fiq,
+        _ => ExceptionType::SError,
+    };
+    let source = match kind / 4 {
+        0 => ExceptionSource::CurrentElSp0,
+        1 => ExceptionSource::CurrentElSpx,
+        2 => ExceptionSource::LowerElAArch64,
+        _ => ExceptionSource::LowerElAArch32,
+    };
+
+    // Let the debugger look at faults, as well as its own breakpoints
+    if exception_type == ExceptionType::Synchronous && gdb::handle_exception(frame) {
+        return;
+    }
+
+    // Otherwise there's nothing that can handle it. The panic's backtrace
+    // continues through the frame record that points at the faulting code.
+    panic!("Unhandled {:?} exception from {:?}\n{}", exception_type, source, frame);
+}
+
+#[cfg(target_arch = "aarch64")]
+global_asm!(include_str!("exceptions.S"));
@@ -0,0 +1,473 @@
+//! A GDB remote stub, so that the kernel can be debugged over a serial port.
+//!
+//! Once attached, breakpoints, single steps and faults stop the kernel inside
+//! the exception handler, and the stub talks to GDB until it's told to carry
+//! on. Only one core is supported, and GDB can't interrupt the kernel while
+//! it's running, because nothing listens to the UART then.
+//!
+//! By default GDB talks to the console, so console output will confuse it
+//! while the kernel is running. The mini UART can be used instead, on its
+//! default pins (GPIO 32 and 33) which don't clash with UART0.
+
+use crate::exceptions::TrapFrame;
+use crate::io::Console;
+use crate::peripherals::{mailbox, power};
+use core::fmt::Write;
+use core::mem;
+use core::ptr;
+use utils::gdb::{self, BreakpointType, Command, PacketReader, Received, Response};
+use utils::sync::Mutex;
+
+/// The largest packet that we accept, which is sent to GDB in qSupported
+const PACKET_SIZE: usize = 1024;
+const MAX_BREAKPOINTS: usize = 16;
+/// `BRK #0`
+const BRK_INSTRUCTION: u32 = 0xD420_0000;
+
+// GDB's numbering of the AArch64 registers
+const SP_REGISTER: usize = 31;
+const PC_REGISTER: usize = 32;
+const CPSR_REGISTER: usize = 33;
+
+/// MDSCR_EL1.SS enables software stepping, and MDSCR_EL1.KDE lets the debug
+/// exceptions that it causes be taken at EL1
+const MDSCR_SS: u64 = 1 << 0;
+const MDSCR_KDE: u64 = 1 << 13;
+/// In SPSR, SS steps one instruction after returning, and D masks debug
+/// exceptions
+const SPSR_SS: u64 = 1 << 21;
+const SPSR_D: u64 = 1 << 9;
+
+// Signals for the stop replies
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    address: u64,
+    /// The instruction that the BRK replaced
+    original: u32,
+}
+
+struct Stub {
+    console: Option<&'static dyn Console>,
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    no_ack: bool,
+    /// Whether GDB is waiting for a stop reply after continuing or stepping
+    running: bool,
+    /// The D bit from before a single step, to put back afterwards
+    stepping: Option<u64>,
+    /// The start and end of the ARM's RAM. Memory accesses are limited to
+    /// this, so that GDB can't touch the peripherals.
+    memory: (u64, u64),
+}
+
+static STUB: Mutex<Stub> = Mutex::new(Stub {
+    console: None,
+    breakpoints: [None; MAX_BREAKPOINTS],
+    no_ack: false,
+    running: false,
+    stepping: None,
+    memory: (0, 0),
+});
+
+/// The packet buffers, which are kept here because they're too big for the
+/// exception handler's stack
+struct Buffers {
+    reader: PacketReader<PACKET_SIZE>,
+    response: Response<PACKET_SIZE>,
+}
+
+static BUFFERS: Mutex<Buffers> = Mutex::new(Buffers {
+    reader: PacketReader::new(),
+    response: Response::new(),
+});
+
+/// Start handling debug exceptions, and talk to GDB through `console`
+pub fn attach(console: &'static dyn Console) -> mailbox::Result<()> {
+    let (base, length) = mailbox::get_memory_range()?;
+    let mut stub = STUB.lock();
+    stub.memory = (u64::from(base), u64::from(base) + u64::from(length));
+    stub.console = Some(console);
+    drop(stub);
+    unlock_debug();
+    Ok(())
+}
+
+/// Whether the stub is attached, so exceptions will stop the kernel
+pub fn is_attached() -> bool {
+    STUB.lock().console.is_some()
+}
+
+/// Stop in the stub, as if a breakpoint had been hit
+#[cfg(target_arch = "aarch64")]
+pub fn breakpoint() {
+    unsafe {
+        asm!("BRK     #0" :::: "volatile");
+    }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+pub fn breakpoint() {
+}
+
+/// Hand a synchronous exception to GDB. Returns false if the stub isn't
+/// attached, in which case the exception still needs handling.
+pub fn handle_exception(frame: &mut TrapFrame) -> bool {
+    // If the stub is already locked then the exception came from inside the
+    // stub, and waiting for the lock would hang rather than panicking
+    let mut stub = match STUB.try_lock() {
+        Some(stub) => stub,
+        None => return false,
+    };
+    let console = match stub.console {
+        Some(console) => console,
+        None => return false,
+    };
+
+    let signal = match (frame.esr >> 26) & 0x3F {
+        // BRK
+        0x3C => {
+            // Step over BRKs that are compiled in, like the one in
+            // `breakpoint`, rather than stopping on them forever. GDB takes
+            // care of stepping over its own.
+            if !stub.breakpoints.iter().flatten().any(|b| b.address == frame.elr) {
+                frame.elr += 4;
+            }
+            SIGTRAP
+        },
+        // Software step
+        0x32 | 0x33 => SIGTRAP,
+        // Instruction and data aborts, and alignment faults
+        0x20 | 0x21 | 0x22 | 0x24 | 0x25 | 0x26 => SIGSEGV,
+        _ => SIGILL,
+    };
+    if let Some(d) = stub.stepping.take() {
+        set_mdscr(get_mdscr() & !MDSCR_SS);
+        frame.spsr = (frame.spsr & !(SPSR_SS | SPSR_D)) | d;
+    }
+
+    let mut buffers = BUFFERS.lock();
+    let buffers = &mut *buffers;
+    buffers.reader.reset();
+    let mut session = Session {
+        stub: &mut stub,
+        console,
+        reader: &mut buffers.reader,
+        response: &mut buffers.response,
+        signal,
+    };
+    if session.stub.running {
+        session.stub.running = false;
+        session.stop_reply();
+    }
+    session.run(frame);
+    true
+}
+
+/// Talks to GDB while the kernel is stopped
+struct Session<'a> {
+    stub: &'a mut Stub,
+    console: &'static dyn Console,
+    reader: &'a mut PacketReader<PACKET_SIZE>,
+    response: &'a mut Response<PACKET_SIZE>,
+    signal: u8,
+}
+
+impl<'a> Session<'a> {
+    /// Handle packets until GDB says to resume
+    fn run(&mut self, frame: &mut TrapFrame) {
+        loop {
+            match self.reader.feed(self.console.read_byte()) {
+                Some(Received::Packet) => {
+                    if !self.stub.no_ack {
+                        self.console.write_byte(b'+');
+                    }
+                },
+                Some(Received::Corrupt) => {
+                    self.console.write_byte(b'-');
+                    continue;
+                },
+                // We're already stopped
+                Some(Received::Interrupt) | None => continue,
+            }
+
+            self.response.clear();
+            // The response is big enough for anything we send, and GDB
+            // can cope with a truncated one if it isn't
+            let _ = match Command::parse(self.reader.packet()) {
+                Command::HaltReason => write!(self.response, "S{:02x}", self.signal),
+                Command::ReadRegisters => read_registers(frame, self.response),
+                Command::WriteRegisters(hex) => write_registers(frame, hex, self.response),
+                Command::ReadRegister(n) => match register(frame, n) {
+                    Some(value) => self.response.push_hex(&value[..register_size(n)]),
+                    None => write!(self.response, "E01"),
+                },
+                Command::WriteRegister(n, hex) => {
+                    let mut value = [0; 8];
+                    match gdb::decode_hex(hex, &mut value[..register_size(n)]) {
+                        Some(()) if set_register(frame, n, u64::from_le_bytes(value)) => write!(self.response, "OK"),
+                        _ => write!(self.response, "E01"),
+                    }
+                },
+                Command::ReadMemory { address, length } => read_memory(self.stub.memory, address, length, self.response),
+                Command::WriteMemory { address, data } => write_memory(self.stub.memory, address, data, self.response),
+                Command::InsertBreakpoint(BreakpointType::Software, address) => {
+                    let result = self.stub.insert_breakpoint(address);
+                    self.ok_or_error(result)
+                },
+                Command::RemoveBreakpoint(BreakpointType::Software, address) => {
+                    let result = self.stub.remove_breakpoint(address);
+                    self.ok_or_error(result)
+                },
+                Command::Continue(address) => return self.resume(frame, address, false),
+                Command::Step(address) => return self.resume(frame, address, true),
+                Command::QuerySupported => write!(self.response, "PacketSize={:x};QStartNoAckMode+", PACKET_SIZE),
+                Command::StartNoAckMode => {
+                    self.stub.no_ack = true;
+                    write!(self.response, "OK")
+                },
+                Command::Detach => {
+                    self.stub.remove_all_breakpoints();
+                    self.stub.console = None;
+                    self.stub.no_ack = false;
+                    let _ = write!(self.response, "OK");
+                    self.send();
+                    return;
+                },
+                Command::Kill => power::get_power_manager().reboot(),
+                _ => Ok(()),
+            };
+            self.send();
+        }
+    }
+
+    /// Let the kernel carry on, optionally from a different address, and
+    /// stop again after one instruction if `step` is set
+    fn resume(&mut self, frame: &mut TrapFrame, address: Option<u64>, step: bool) {
+        if let Some(address) = address {
+            frame.elr = address;
+        }
+        if step {
+            self.stub.stepping = Some(frame.spsr & SPSR_D);
+            set_mdscr(get_mdscr() | MDSCR_SS | MDSCR_KDE);
+            frame.spsr = (frame.spsr | SPSR_SS) & !SPSR_D;
+        }
+        self.stub.running = true;
+    }
+
+    fn ok_or_error(&mut self, result: bool) -> core::fmt::Result {
+        if result {
+            write!(self.response, "OK")
+        } else {
+            write!(self.response, "E01")
+        }
+    }
+
+    fn stop_reply(&mut self) {
+        self.response.clear();
+        let _ = write!(self.response, "S{:02x}", self.signal);
+        self.send();
+    }
+
+    fn send(&self) {
+        let console = self.console;
+        self.response.send(|b| console.write_byte(b));
+    }
+}
+
+impl Stub {
+    fn insert_breakpoint(&mut self, address: u64) -> bool {
+        if self.breakpoints.iter().flatten().any(|b| b.address == address) {
+            return true;
+        }
+        if address & 3 != 0 || !valid_range(self.memory, address, 4) {
+            return false;
+        }
+        let slot = match self.breakpoints.iter_mut().find(|b| b.is_none()) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        unsafe {
+            let instruction = address as *mut u32;
+            *slot = Some(Breakpoint { address, original: ptr::read_volatile(instruction) });
+            ptr::write_volatile(instruction, BRK_INSTRUCTION);
+        }
+        sync_instruction_cache();
+        true
+    }
+
+    fn remove_breakpoint(&mut self, address: u64) -> bool {
+        let slot = match self.breakpoints.iter_mut().find(|b| matches!(b, Some(b) if b.address == address)) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        if let Some(breakpoint) = slot.take() {
+            unsafe {
+                ptr::write_volatile(breakpoint.address as *mut u32, breakpoint.original);
+            }
+        }
+        sync_instruction_cache();
+        true
+    }
+
+    fn remove_all_breakpoints(&mut self) {
+        for slot in self.breakpoints.iter_mut() {
+            if let Some(breakpoint) = slot.take() {
+                unsafe {
+                    ptr::write_volatile(breakpoint.address as *mut u32, breakpoint.original);
+                }
+            }
+        }
+        sync_instruction_cache();
+    }
+}
+
+/// The number of bytes in a register, as GDB sees it
+fn register_size(n: usize) -> usize {
+    if n == CPSR_REGISTER { 4 } else { 8 }
+}
+
+/// Get the value of a register as little endian bytes
+fn register(frame: &TrapFrame, n: usize) -> Option<[u8; 8]> {
+    let value = match n {
+        0..=30 => frame.x[n],
+        // The stack pointer from before the exception, which is just above
+        // the trap frame
+        SP_REGISTER => frame as *const TrapFrame as u64 + mem::size_of::<TrapFrame>() as u64,
+        PC_REGISTER => frame.elr,
+        CPSR_REGISTER => frame.spsr,
+        _ => return None,
+    };
+    Some(value.to_le_bytes())
+}
+
+/// Change a register, returning false if it can't be changed
+fn set_register(frame: &mut TrapFrame, n: usize, value: u64) -> bool {
+    match n {
+        0..=30 => frame.x[n] = value,
+        // The trap frame is on the stack, so it can't be moved
+        SP_REGISTER => return value == u64::from_le_bytes(register(frame, n).unwrap()),
+        PC_REGISTER => frame.elr = value,
+        CPSR_REGISTER => frame.spsr = value,
+        _ => return false,
+    }
+    true
+}
+
+fn read_registers(frame: &TrapFrame, response: &mut Response<PACKET_SIZE>) -> core::fmt::Result {
+    for n in 0..=CPSR_REGISTER {
+        let value = register(frame, n).unwrap();
+        response.push_hex(&value[..register_size(n)])?;
+    }
+    Ok(())
+}
+
+fn write_registers(frame: &mut TrapFrame, mut hex: &[u8], response: &mut Response<PACKET_SIZE>) -> core::fmt::Result {
+    for n in 0..=CPSR_REGISTER {
+        let size = register_size(n);
+        let mut value = [0; 8];
+        if hex.len() < size * 2 || gdb::decode_hex(&hex[..size * 2], &mut value[..size]).is_none() {
+            break;
+        }
+        hex = &hex[size * 2..];
+        // GDB sends the stack pointer back unchanged, so ignore it here
+        if n != SP_REGISTER {
+            set_register(frame, n, u64::from_le_bytes(value));
+        }
+    }
+    write!(response, "OK")
+}
+
+/// Whether `length` bytes from `address` are all within `memory`
+fn valid_range(memory: (u64, u64), address: u64, length: usize) -> bool {
+    address >= memory.0
+        && matches!(address.checked_add(length as u64), Some(end) if end <= memory.1)
+}
+
+fn read_memory(memory: (u64, u64), address: u64, length: usize, response: &mut Response<PACKET_SIZE>) -> core::fmt::Result {
+    // Send as much as fits, and GDB will ask for the rest
+    let length = length.min(PACKET_SIZE / 2);
+    if !valid_range(memory, address, length) {
+        return write!(response, "E01");
+    }
+    for i in 0..length as u64 {
+        let byte = unsafe { ptr::read_volatile((address + i) as *const u8) };
+        response.push_hex(&[byte])?;
+    }
+    Ok(())
+}
+
+fn write_memory(memory: (u64, u64), address: u64, hex: &[u8], response: &mut Response<PACKET_SIZE>) -> core::fmt::Result {
+    if !valid_range(memory, address, hex.len() / 2) {
+        return write!(response, "E01");
+    }
+    for (i, pair) in hex.chunks(2).enumerate() {
+        let mut byte = [0];
+        if gdb::decode_hex(pair, &mut byte).is_none() {
+            return write!(response, "E01");
+        }
+        unsafe {
+            ptr::write_volatile((address + i as u64) as *mut u8, byte[0]);
+        }
+    }
+    // The memory could have been code
+    sync_instruction_cache();
+    write!(response, "OK")
+}
+
+/// Make sure that instructions that have just been written are fetched
+#[cfg(target_arch = "aarch64")]
+fn sync_instruction_cache() {
+    unsafe {
+        asm!("DSB     ISH
+              IC      IALLU
+              DSB     ISH
+              ISB" :::: "volatile");
+    }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+fn sync_instruction_cache() {
+}
+
+/// Clear the OS lock, which blocks debug exceptions such as single steps
+/// until it's released
+#[cfg(target_arch = "aarch64")]
+fn unlock_debug() {
+    unsafe {
+        asm!("MSR     OSLAR_EL1, xzr
+              ISB" :::: "volatile");
+    }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+fn unlock_debug() {
+}
+
+#[cfg(target_arch = "aarch64")]
+fn get_mdscr() -> u64 {
+    let mdscr: u64;
+    unsafe {
+        asm!("MRS     $0, MDSCR_EL1" : "=r"(mdscr) ::: "volatile");
+    }
+    mdscr
+}
+
+#[cfg(target_arch = "aarch64")]
+fn set_mdscr(mdscr: u64) {
+    unsafe {
+        asm!("MSR     MDSCR_EL1, $0
+              ISB" :: "r"(mdscr) :: "volatile");
+    }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+fn get_mdscr() -> u64 {
+    0
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+fn set_mdscr(_mdscr: u64) {
+}
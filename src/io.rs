@@ -1,24 +1,236 @@
+pub mod console;
+pub mod emergency;
 pub mod macros;
+pub mod sink;
 pub mod uart;
 
-use crate::peripherals::uart0::Uart;
+use crate::log::{self, Level};
+#[cfg(feature = "lock-debug")]
 use self::uart::UartWriter;
-use utils::sync::Mutex;
+use utils::sync::{IrqSafeMutex, IrqSafeRwLock};
 use core::fmt;
-use core::mem;
 
+pub use self::console::Console;
 pub use self::macros::*;
+pub use self::sink::Sink;
+
+/// The maximum number of sinks that output can be sent to at once
+const MAX_SINKS: usize = 8;
+/// The log buffer is always the first sink
+const LOG_BUFFER_SINK: SinkId = SinkId(0);
+
+#[derive(Debug)]
+pub enum IoError {
+    /// There is no space left to add another sink
+    TooManySinks,
+    /// The sink has already been removed
+    UnknownSink(SinkId),
+}
+pub type Result<T> = ::core::result::Result<T, IoError>;
+
+/// Identifies a sink that has been added, so that it can be changed or
+/// removed later
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SinkId(usize);
+
+#[derive(Clone, Copy)]
+struct SinkEntry {
+    id: SinkId,
+    sink: &'static dyn Sink,
+    /// The least important log messages that the sink is sent
+    level: Level,
+}
+
+struct Sinks {
+    entries: [Option<SinkEntry>; MAX_SINKS],
+    next_id: usize,
+    /// The sink for the device that `set_console` installed
+    console: Option<SinkId>,
+}
+
+impl Sinks {
+    fn add(&mut self, sink: &'static dyn Sink, level: Level) -> Result<SinkId> {
+        let id = SinkId(self.next_id);
+        match self.entries.iter_mut().find(|e| e.is_none()) {
+            Some(slot) => *slot = Some(SinkEntry { id, sink, level }),
+            None => return Err(IoError::TooManySinks),
+        }
+        self.next_id += 1;
+        Ok(id)
+    }
+
+    fn find(&mut self, id: SinkId) -> Result<&mut Option<SinkEntry>> {
+        self.entries.iter_mut()
+            .find(|e| e.map_or(false, |e| e.id == id))
+            .ok_or(IoError::UnknownSink(id))
+    }
+
+    fn console_sinks(&self) -> impl Iterator<Item = &'static dyn Sink> + '_ {
+        self.entries.iter()
+            .flatten()
+            .map(|entry| entry.sink)
+            .filter(|sink| sink.wants_console_output())
+    }
+}
+
+/// Sends everything written to it to each of the sinks that want it
+struct Fanout<'a> {
+    sinks: &'a Sinks,
+    /// The level of the log message being written, or None for console
+    /// output
+    level: Option<Level>,
+}
+
+impl<'a> fmt::Write for Fanout<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for entry in self.sinks.entries.iter().flatten() {
+            let wanted = match self.level {
+                Some(level) => level <= entry.level,
+                // Until there's a console, keep console output in the log
+                // buffer so that it's replayed once there is one
+                None => entry.sink.wants_console_output()
+                    || (self.sinks.console.is_none() && entry.id == LOG_BUFFER_SINK),
+            };
+            if wanted {
+                entry.sink.write_str(s);
+            }
+        }
+        Ok(())
+    }
+}
 
 pub fn _print(args: fmt::Arguments) {
-    match STDOUT.lock().value_mut() {
-        None => (),
-        Some(uart_writer) => fmt::write(uart_writer, args).unwrap()
+    if emergency::is_active() {
+        emergency::stop_if_not_owner();
+        let _ = fmt::write(&mut emergency::EmergencyWriter, args);
+        return;
+    }
+    let sinks = SINKS.lock();
+    let _ = fmt::write(&mut Fanout { sinks: &sinks, level: None }, args);
+}
+
+/// Send a formatted log message to the sinks that want messages at its level
+pub fn write_log(level: Level, line: &str) {
+    if emergency::is_active() {
+        emergency::stop_if_not_owner();
+        emergency::console().puts(line);
+        return;
     }
+    let sinks = SINKS.lock();
+    let _ = fmt::Write::write_str(&mut Fanout { sinks: &sinks, level: Some(level) }, line);
 }
 
-pub fn set_console(uart: &'static Uart) {
-    let mut console = Some(UartWriter::new(uart));
-    mem::swap(STDOUT.lock().value_mut(), &mut console);
+/// Send everything in the log buffer to the sinks that show console output
+pub fn print_log_buffer() {
+    let sinks = SINKS.lock();
+    for sink in sinks.console_sinks() {
+        log::replay(sink);
+    }
 }
 
-static STDOUT: Mutex<Option<UartWriter>> = Mutex::new(None);
+/// Writes to the console in the same way as `print!`, for code that needs a
+/// `fmt::Write`
+pub struct Stdout;
+
+impl fmt::Write for Stdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        _print(format_args!("{}", s));
+        Ok(())
+    }
+}
+
+/// Start sending output to a sink, along with log messages at `level` and
+/// above. The sink is sent the contents of the log buffer first, so that it
+/// shows everything since boot.
+pub fn add_sink(sink: &'static dyn Sink, level: Level) -> Result<SinkId> {
+    let mut sinks = SINKS.lock();
+    let id = sinks.add(sink, level)?;
+    if sink.wants_console_output() {
+        log::replay(sink);
+    }
+    Ok(id)
+}
+
+/// Stop sending output to a sink
+pub fn remove_sink(id: SinkId) -> Result<()> {
+    let mut sinks = SINKS.lock();
+    *sinks.find(id)? = None;
+    if sinks.console == Some(id) {
+        sinks.console = None;
+    }
+    Ok(())
+}
+
+/// Change the least important log messages that a sink is sent
+pub fn set_sink_level(id: SinkId, level: Level) -> Result<()> {
+    let mut sinks = SINKS.lock();
+    if let Some(entry) = sinks.find(id)? {
+        entry.level = level;
+    }
+    Ok(())
+}
+
+/// Read input from the given device, which can be either of the UARTs. Output
+/// is sent to it in place of the previous console, alongside any other sinks.
+pub fn set_console<C: Console>(console: &'static C) -> Result<SinkId> {
+    *CONSOLE.write() = Some(console);
+
+    let mut sinks = SINKS.lock();
+    if let Some(previous) = sinks.console.take() {
+        *sinks.find(previous)? = None;
+    }
+    let id = sinks.add(console, Level::Trace)?;
+    sinks.console = Some(id);
+    log::replay(console);
+    drop(sinks);
+
+    #[cfg(feature = "lock-debug")]
+    utils::sync::debug::set_spin_warning_handler(report_slow_lock);
+
+    Ok(id)
+}
+
+/// Get the device that `set_console` installed, if there is one
+pub fn console() -> Option<&'static dyn Console> {
+    *CONSOLE.read()
+}
+
+/// Warn that a core has been waiting for a lock for a long time. The lock
+/// being waited for could be SINKS, so this writes to the console directly.
+#[cfg(feature = "lock-debug")]
+fn report_slow_lock(slow: &utils::sync::debug::SlowLock) {
+    let console = match CONSOLE.try_read() {
+        Some(console) => *console,
+        None => None,
+    };
+    let mut writer = match console {
+        Some(console) => UartWriter::new(console),
+        None => return,
+    };
+    let _ = fmt::write(&mut writer, format_args!(
+        "\nCore {} has spun {} times waiting for lock {:#x} at {}\n",
+        utils::cpu::core_id(), slow.spins, slow.address, slow.waiter
+    ));
+    if let Some(holder) = slow.holder {
+        let _ = fmt::write(&mut writer, format_args!(
+            "  held by core {} since {}\n", holder.core, holder.location
+        ));
+    }
+}
+
+// The current console device, for input and for code that can't wait for
+// SINKS. Interrupt handlers may print, so this masks IRQs too.
+static CONSOLE: IrqSafeRwLock<Option<&'static dyn Console>> = IrqSafeRwLock::new(None);
+
+// Output is written to every sink while holding the lock, so that output from
+// different cores isn't interleaved. Interrupt handlers may print, so the lock
+// has to mask IRQs while it's held. The log buffer is there from the start so
+// that nothing logged before the console is up gets lost.
+static SINKS: IrqSafeMutex<Sinks> = IrqSafeMutex::new(Sinks {
+    entries: [
+        Some(SinkEntry { id: LOG_BUFFER_SINK, sink: &log::LOG_BUFFER, level: Level::Trace }),
+        None, None, None, None, None, None, None,
+    ],
+    next_id: 1,
+    console: None,
+});
@@ -0,0 +1,46 @@
+/// A byte-oriented device that can be used as the console, such as one of the
+/// UARTs. Text is sent as UTF-8; use `utils::utf8::Utf8Reader` to decode
+/// characters from `read_byte`.
+pub trait Console: Sync {
+    /// Block until a byte is received, and return it
+    fn read_byte(&self) -> u8;
+
+    /// Return a byte if one has been received, without waiting
+    fn try_read_byte(&self) -> Option<u8>;
+
+    /// Send a single byte
+    fn write_byte(&self, byte: u8);
+
+    /// Wait until everything that has been written has been sent
+    fn flush(&self);
+
+    /// Fill `buf` with received bytes
+    fn read_exact(&self, buf: &mut [u8]) {
+        for byte in buf {
+            *byte = self.read_byte();
+        }
+    }
+
+    /// Send all of `bytes`, without any translation
+    fn write_all(&self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_byte(byte);
+        }
+    }
+
+    /// Send a single character
+    fn send(&self, c: char) {
+        let mut buf = [0; 4];
+        self.write_all(c.encode_utf8(&mut buf).as_bytes());
+    }
+
+    /// Send a string, translating "\n" to "\r\n"
+    fn puts(&self, string: &str) {
+        for c in string.chars() {
+            if c == '\n' {
+                self.send('\r');
+            }
+            self.send(c);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! A console for panics, which can be used whatever state the normal console
+//! was left in.
+//!
+//! Once a core has taken over the emergency console, all output goes straight
+//! to the console device without taking any locks. The lock on the sinks may
+//! have been held by the core that panicked, so waiting for it could deadlock.
+//!
+//! Taking over also tells the other cores to stop, but there's no way to
+//! interrupt them yet, so each one only stops when it next checks: when it
+//! prints, logs or takes an exception. Until then it may still be running.
+
+use crate::io::{Console, CONSOLE};
+use crate::peripherals::uart0;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::hint::spin_loop;
+use utils::cpu::{self, MAX_CORES};
+
+/// Who was using the emergency console when it was taken over
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Takeover {
+    /// This core now owns the emergency console
+    Taken,
+    /// This core already owned it, so it must have panicked again
+    AlreadyOwned,
+    /// Another core owns it, so this core should stop
+    OtherCore,
+}
+
+/// Take over the console, so that nothing else can print while panicking.
+/// After this, `print!` and the log macros on this core write directly to the
+/// console device, and other cores stop the next time they call
+/// `stop_if_not_owner`.
+pub fn take_over() -> Takeover {
+    let core = cpu::core_id();
+    if is_active() {
+        return if OWNER.load(Ordering::Relaxed) == core {
+            Takeover::AlreadyOwned
+        } else {
+            Takeover::OtherCore
+        };
+    }
+    if !claim(core) {
+        return Takeover::OtherCore;
+    }
+    OWNER.store(core, Ordering::Relaxed);
+    ACTIVE.store(true, Ordering::Release);
+    Takeover::Taken
+}
+
+/// Decide which core gets the emergency console, if several panic at once,
+/// and return whether it's this one. The first core to finish taking a ticket
+/// wins, with ties going to the lower core ID, as in Lamport's bakery
+/// algorithm.
+///
+/// This only needs loads and stores, so it's still safe without exclusive
+/// access to memory (where `Mutex::try_lock` is a separate load and store,
+/// which two cores could both get through). The ordering relies on SeqCst
+/// loads and stores, which are LDAR and STLR on aarch64.
+fn claim(core: usize) -> bool {
+    CHOOSING[core].store(true, Ordering::SeqCst);
+    let ticket = 1 + TICKETS.iter().map(|t| t.load(Ordering::SeqCst)).max().unwrap_or(0);
+    TICKETS[core].store(ticket, Ordering::SeqCst);
+    CHOOSING[core].store(false, Ordering::SeqCst);
+
+    for other in (0..MAX_CORES).filter(|&other| other != core) {
+        while CHOOSING[other].load(Ordering::SeqCst) {
+            spin_loop();
+        }
+        // Tickets are never given back, so a core with an earlier ticket has
+        // won, or will win
+        let theirs = TICKETS[other].load(Ordering::SeqCst);
+        if theirs != 0 && (theirs, other) < (ticket, core) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Whether a core has taken over the emergency console
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Acquire)
+}
+
+/// Stop this core if another core has taken over the emergency console. This
+/// is how the other cores are stopped while one is panicking, so it should be
+/// called from anywhere that a core passes through regularly.
+pub fn stop_if_not_owner() {
+    if is_active() && OWNER.load(Ordering::Relaxed) != cpu::core_id() {
+        cpu::park();
+    }
+}
+
+/// The device to write emergency output to. This is the current console if
+/// there is one, which is used as it is. Otherwise UART0 is used, and only
+/// initialised if it isn't already running, because changing clocks through
+/// the mailbox is best avoided mid-panic.
+pub fn console() -> &'static dyn Console {
+    // The other cores have stopped by now, or will stop before they print, so
+    // the console won't change under us even if its lock is held
+    if let Some(console) = unsafe { *CONSOLE.read_unchecked() } {
+        return console;
+    }
+    let uart = uart0::get_uart();
+    if !uart.is_enabled() {
+        let _ = uart.init();
+    }
+    uart
+}
+
+/// Writes to the emergency console
+pub struct EmergencyWriter;
+
+impl fmt::Write for EmergencyWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        console().puts(s);
+        Ok(())
+    }
+}
+
+// The state of `claim` for each core. A ticket of 0 means that the core
+// hasn't tried to take over.
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_CHOOSING: AtomicBool = AtomicBool::new(false);
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_TICKET: AtomicUsize = AtomicUsize::new(0);
+static CHOOSING: [AtomicBool; MAX_CORES] = [NOT_CHOOSING; MAX_CORES];
+static TICKETS: [AtomicUsize; MAX_CORES] = [NO_TICKET; MAX_CORES];
+
+static OWNER: AtomicUsize = AtomicUsize::new(0);
+// Set once a core owns the emergency console, which also tells the other
+// cores to stop
+static ACTIVE: AtomicBool = AtomicBool::new(false);
@@ -0,0 +1,19 @@
+use crate::io::Console;
+
+/// Somewhere that console output and log messages can be sent, such as a
+/// UART or the frame buffer
+pub trait Sink: Sync {
+    fn write_str(&self, s: &str);
+
+    /// Whether the sink wants console output from `print!` as well as log
+    /// messages
+    fn wants_console_output(&self) -> bool {
+        true
+    }
+}
+
+impl<T: Console> Sink for T {
+    fn write_str(&self, s: &str) {
+        self.puts(s);
+    }
+}
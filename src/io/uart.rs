@@ -1,12 +1,12 @@
-use crate::peripherals::uart0::Uart;
+use crate::io::Console;
 use core::fmt;
 
 pub struct UartWriter {
-    uart: &'static Uart,
+    uart: &'static dyn Console,
 }
 
 impl UartWriter {
-    pub fn new(uart: &'static Uart) -> UartWriter {
+    pub fn new(uart: &'static dyn Console) -> UartWriter {
         UartWriter {
             uart: uart,
         }
@@ -0,0 +1,226 @@
+use crate::io::{self, Sink};
+use crate::peripherals::timer;
+use core::fmt::{self, Write};
+use utils::log_buffer::LogBuffer;
+use utils::sync::{IrqSafeMutex, IrqSafeRwLock};
+
+/// The number of bytes of log messages to keep
+const LOG_BUFFER_SIZE: usize = 16 * 1024;
+/// Longer lines are truncated
+const MAX_LINE_LENGTH: usize = 256;
+/// The maximum number of modules that can have their own level
+const MAX_FILTERS: usize = 16;
+
+/// The importance of a log message. Messages are logged if their level is
+/// at or above the level set for their module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LogError {
+    /// There is no space left to set the level of another module
+    TooManyFilters,
+}
+
+struct Filters {
+    default: Level,
+    modules: [Option<(&'static str, Level)>; MAX_FILTERS],
+}
+
+impl Filters {
+    /// The level for a module, which comes from the filter for the most
+    /// specific module path that contains it
+    fn level(&self, module: &str) -> Level {
+        let mut best: Option<(&'static str, Level)> = None;
+        for &(path, level) in self.modules.iter().flatten() {
+            let matches = module == path
+                || (module.starts_with(path) && module[path.len()..].starts_with("::"));
+            if matches && best.map_or(true, |(best_path, _)| path.len() > best_path.len()) {
+                best = Some((path, level));
+            }
+        }
+        best.map_or(self.default, |(_, level)| level)
+    }
+}
+
+// Checked for every log message, including those from interrupt handlers, so
+// this has to mask IRQs
+static FILTERS: IrqSafeRwLock<Filters> = IrqSafeRwLock::new(Filters {
+    default: Level::Info,
+    modules: [None; MAX_FILTERS],
+});
+
+/// Keeps the most recent log messages, so that they can be replayed when a
+/// new sink is added. It is always one of the sinks.
+pub struct LogBufferSink {
+    // Interrupt handlers may log, so this has to mask IRQs
+    buffer: IrqSafeMutex<LogBuffer<LOG_BUFFER_SIZE>>,
+}
+
+impl Sink for LogBufferSink {
+    fn write_str(&self, s: &str) {
+        self.buffer.lock().write(s.as_bytes());
+    }
+
+    fn wants_console_output(&self) -> bool {
+        false
+    }
+}
+
+pub static LOG_BUFFER: LogBufferSink = LogBufferSink {
+    buffer: IrqSafeMutex::new(LogBuffer::new()),
+};
+
+/// Set the level for modules that don't have their own
+pub fn set_default_level(level: Level) {
+    FILTERS.write().default = level;
+}
+
+/// Set the level for a module and the modules inside it, such as
+/// `"kernel::peripherals"`
+pub fn set_level(module: &'static str, level: Level) -> Result<(), LogError> {
+    let mut filters = FILTERS.write();
+    if let Some(filter) = filters.modules.iter_mut().flatten().find(|(path, _)| *path == module) {
+        filter.1 = level;
+        return Ok(());
+    }
+    match filters.modules.iter_mut().find(|f| f.is_none()) {
+        Some(slot) => {
+            *slot = Some((module, level));
+            Ok(())
+        },
+        None => Err(LogError::TooManyFilters),
+    }
+}
+
+/// Whether messages at `level` from `module` will be logged
+pub fn enabled(level: Level, module: &str) -> bool {
+    level <= FILTERS.read().level(module)
+}
+
+pub fn _log(level: Level, module: &str, args: fmt::Arguments) {
+    if !enabled(level, module) {
+        return;
+    }
+
+    let us = timer::get_timer().read_timer();
+    let mut line = Line::new();
+    let _ = write!(line, "[{:5}.{:06}] {:<5} {}: ", us / 1_000_000, us % 1_000_000, level.name(), module);
+    let _ = line.write_fmt(args);
+    line.end();
+
+    io::write_log(level, line.as_str());
+}
+
+/// Print everything in the log buffer to the console
+pub fn print_buffer() {
+    io::print_log_buffer();
+}
+
+/// Send everything in the log buffer to a sink
+pub fn replay(sink: &dyn Sink) {
+    let log = LOG_BUFFER.buffer.lock();
+    let (older, newer) = log.contents();
+    for part in [older, newer].iter() {
+        // The buffer only ever holds whole lines of text
+        let text = match core::str::from_utf8(part) {
+            Ok(text) => text,
+            Err(e) => core::str::from_utf8(&part[..e.valid_up_to()]).unwrap_or(""),
+        };
+        sink.write_str(text);
+    }
+}
+
+/// A single formatted log message
+struct Line {
+    buf: [u8; MAX_LINE_LENGTH],
+    len: usize,
+}
+
+impl Line {
+    fn new() -> Line {
+        Line {
+            buf: [0; MAX_LINE_LENGTH],
+            len: 0,
+        }
+    }
+
+    /// Finish the line with a newline, which there is always space for
+    fn end(&mut self) {
+        self.buf[self.len] = b'\n';
+        self.len += 1;
+    }
+
+    fn as_str(&self) -> &str {
+        // Only whole characters are ever written
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+impl fmt::Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Leave space for the newline, and truncate at a character boundary
+        let space = MAX_LINE_LENGTH - 1 - self.len;
+        let mut len = s.len().min(space);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        if len < s.len() {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        $crate::log::_log($level, module_path!(), format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Error, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Warn, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Info, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Debug, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Trace, $($arg)*));
+}
@@ -8,18 +8,48 @@
 #![feature(never_type)]
 #![feature(format_args_nl)]
 
+mod backtrace;
+mod crash_record;
 mod display;
+mod exceptions;
+mod gdb;
 mod peripherals;
 mod io;
+mod log;
 mod panic_handler;
 mod self_update;
+mod shell;
+mod symbols;
 
 fn entry() -> ! {
+    exceptions::init();
+    info!("Kernel started");
+
     let uart = peripherals::uart0::get_uart();
 
-    uart.init().unwrap();
-    
-    io::set_console(uart);
+    let baud_rate = uart.init().unwrap();
+    info!("UART0 running at {} baud", baud_rate.actual);
+
+    io::set_console(uart).unwrap();
+
+    if crash_record::check() {
+        crash_record::with_previous(|previous| {
+            if let Some(previous) = previous {
+                warn!("previous boot crashed: {} (run `lastcrash` for details)", previous.summary());
+            }
+        });
+    }
+
+    // Show the same output on a monitor, if there is one
+    let text_console = display::text_console::get_text_console();
+    match text_console.init(1920, 1080) {
+        Ok(()) => {
+            if let Err(e) = io::add_sink(text_console, log::Level::Trace) {
+                warn!("Failed to show the console on the frame buffer: {:?}", e);
+            }
+        },
+        Err(e) => warn!("Failed to create a frame buffer for the console: {:?}", e),
+    }
 
     println!("Hello!");
 
@@ -46,29 +76,9 @@ fn entry() -> ! {
         Err(e) => println!("{:?}", e),
     }
 
-    let rand = peripherals::random::get_rng();
-    rand.init();
-
-    let mut frame_buffer = display::frame_buffer::FrameBuffer::new(1920, 1080).unwrap();
-    frame_buffer.draw();
-
-    loop {
-        let c = uart.getc();
-        if c == '^' {
-            if let Err(e) = self_update::self_update(uart) {
-                println!("{:?}", e);
-            }
-        } else if c == '\n' {
-            println!();
-        } else if c == 'r' {
-            println!("{:#x}", rand.rand());
-        } else if c == 'R' {
-            peripherals::power::get_power_manager().reboot();
-        } else if c == 's' {
-            peripherals::power::get_power_manager().shutdown().unwrap();
-        } else {
-            print!("{}", c);
-        }
+    match shell::run() {
+        Ok(never) => never,
+        Err(e) => panic!("Failed to start the shell: {:?}", e),
     }
 }
 
@@ -1,17 +1,53 @@
-use crate::peripherals::uart0::get_uart;
+use crate::backtrace;
+use crate::crash_record::Recorder;
+use crate::io::emergency::{self, EmergencyWriter, Takeover};
 use crate::peripherals::power;
 
 use core::fmt;
 use core::panic::PanicInfo;
 
+/// Writes to the emergency console, and also keeps a copy for the next boot
+struct PanicWriter {
+    console: EmergencyWriter,
+    recorder: Recorder,
+}
+
+impl fmt::Write for PanicWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.recorder.write_str(s)?;
+        self.console.write_str(s)
+    }
+}
+
 #[panic_handler]
 #[allow(unused_must_use)]
 #[cfg(not(test))]
 fn panic(info: &PanicInfo) -> ! {
-    let mut uart = unsafe {&mut*(get_uart() as *const crate::peripherals::uart0::Uart as *mut crate::peripherals::uart0::Uart)};
-    uart.init();
-    
-    fmt::write(&mut uart, format_args!("{:?}", info));
+    match emergency::take_over() {
+        Takeover::Taken => (),
+        Takeover::AlreadyOwned => {
+            // Formatting the first panic must have panicked, so don't try again
+            emergency::console().puts("\nPanicked while panicking\n");
+            power::get_power_manager().reboot()
+        },
+        // Leave the core that panicked first to report it
+        Takeover::OtherCore => utils::cpu::park(),
+    }
+
+    emergency::console().puts("\n");
+    let mut out = PanicWriter {
+        console: EmergencyWriter,
+        recorder: Recorder::start(),
+    };
+    fmt::write(&mut out, format_args!("{}\n", info));
+    backtrace::print(&mut out, utils::backtrace::frame_pointer());
+
+    #[cfg(feature = "lock-debug")]
+    for lock in utils::sync::debug::held_locks() {
+        fmt::write(&mut out, format_args!("Lock {:#x} held by core {} since {}\n",
+            lock.address, lock.core, lock.location));
+    }
 
+    out.recorder.finish();
     power::get_power_manager().reboot()
 }
@@ -0,0 +1,275 @@
+use crate::peripherals::MMIO_BASE;
+use crate::peripherals::timer::sleep_cycles;
+use crate::peripherals::gpio;
+use crate::peripherals::mailbox;
+use crate::peripherals::uart0::{BaudRate, Result, UartError};
+use crate::io::Console;
+use core::hint::spin_loop;
+use core::fmt::Write;
+use register::{mmio::*, register_bitfields};
+use utils::baud_rate;
+
+// Mini UART (UART1) registers, which live in the auxiliary peripheral block
+// alongside SPI1 and SPI2.
+//
+// Descriptions taken from
+// https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf
+register_bitfields! {
+    u32,
+
+    /// Auxiliary enables
+    AUX_ENABLES [
+        /// If set the mini UART is enabled. The UART will immediately
+        /// start receiving data, especially if the UART1_RX line is
+        /// low. If clear the mini UART is disabled. That also disables
+        /// any mini UART register access.
+        MINI_UART OFFSET(0) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ]
+    ],
+
+    /// Mini UART Interrupt Identify
+    MU_IIR [
+        /// On write: writing with bit 1 set will clear the receive FIFO,
+        /// writing with bit 2 set will clear the transmit FIFO
+        FIFO_CLEAR OFFSET(1) NUMBITS(2) [
+            Rx = 0b01,
+            Tx = 0b10,
+            All = 0b11
+        ]
+    ],
+
+    /// Mini UART Line Control
+    MU_LCR [
+        /// The data sizes that the mini UART supports. Bit 1 is not
+        /// documented, but has to be set to get 8 bit mode.
+        DATA_SIZE OFFSET(0) NUMBITS(2) [
+            SevenBit = 0b00,
+            EightBit = 0b11
+        ]
+    ],
+
+    /// Mini UART Line Status
+    MU_LSR [
+        /// This bit is set if the transmit FIFO is empty and the
+        /// transmitter is idle (finished shifting out the last bit).
+        TX_IDLE OFFSET(6) NUMBITS(1) [],
+
+        /// This bit is set if the transmit FIFO can accept at least one
+        /// byte.
+        TX_EMPTY OFFSET(5) NUMBITS(1) [],
+
+        /// This bit is set if the receive FIFO holds at least 1 symbol.
+        DATA_READY OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Mini UART Extra Control
+    MU_CNTL [
+        /// If this bit is set the mini UART transmitter is enabled.
+        TX_ENABLE OFFSET(1) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
+        /// If this bit is set the mini UART receiver is enabled.
+        RX_ENABLE OFFSET(0) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ]
+    ],
+
+    /// Mini UART Baudrate
+    MU_BAUD [
+        /// mini UART baudrate counter
+        BAUD OFFSET(0) NUMBITS(16) []
+    ]
+}
+
+pub fn get_mini_uart() -> &'static MiniUart {
+    unsafe {
+        &*((MMIO_BASE + 0x0021_5000) as *const MiniUart)
+    }
+}
+
+/// Route TXD1 and RXD1 to the given pins, with the pull up/down resistors
+/// disabled
+fn configure_pins(pins: MiniUartPins) {
+    match pins {
+        MiniUartPins::Gpio14And15 => {
+            gpio::GPFSEL1.modify(gpio::GPFSEL1::FSEL14::TXD1 + gpio::GPFSEL1::FSEL15::RXD1);
+        },
+        MiniUartPins::Gpio32And33 => {
+            gpio::GPFSEL3.modify(gpio::GPFSEL3::FSEL32::TXD1 + gpio::GPFSEL3::FSEL33::RXD1);
+        },
+        MiniUartPins::Gpio40And41 => {
+            gpio::GPFSEL4.modify(gpio::GPFSEL4::FSEL40::TXD1 + gpio::GPFSEL4::FSEL41::RXD1);
+        },
+    }
+    gpio::GPPUD.set(0);
+
+    sleep_cycles(150);
+
+    match pins {
+        MiniUartPins::Gpio14And15 => gpio::GPPUDCLK0.write(
+            gpio::GPPUDCLK0::PUDCLK14::AssertClock + gpio::GPPUDCLK0::PUDCLK15::AssertClock
+        ),
+        MiniUartPins::Gpio32And33 => gpio::GPPUDCLK1.write(
+            gpio::GPPUDCLK1::PUDCLK32::AssertClock + gpio::GPPUDCLK1::PUDCLK33::AssertClock
+        ),
+        MiniUartPins::Gpio40And41 => gpio::GPPUDCLK1.write(
+            gpio::GPPUDCLK1::PUDCLK40::AssertClock + gpio::GPPUDCLK1::PUDCLK41::AssertClock
+        ),
+    }
+
+    sleep_cycles(150);
+
+    gpio::GPPUDCLK0.set(0);
+    gpio::GPPUDCLK1.set(0);
+}
+
+#[allow(non_snake_case)]
+#[repr(C)]
+pub struct MiniUart {
+    AUX_IRQ: ReadOnly<u32>,                               // 0x00
+    AUX_ENABLES: ReadWrite<u32, AUX_ENABLES::Register>,   // 0x04
+    __reserved_0: [u32; 14],                              // 0x08
+    MU_IO: ReadWrite<u32>,                                // 0x40
+    MU_IER: ReadWrite<u32>,                               // 0x44
+    MU_IIR: ReadWrite<u32, MU_IIR::Register>,             // 0x48
+    MU_LCR: ReadWrite<u32, MU_LCR::Register>,             // 0x4C
+    MU_MCR: ReadWrite<u32>,                               // 0x50
+    MU_LSR: ReadOnly<u32, MU_LSR::Register>,              // 0x54
+    MU_MSR: ReadOnly<u32>,                                // 0x58
+    MU_SCRATCH: ReadWrite<u32>,                           // 0x5C
+    MU_CNTL: ReadWrite<u32, MU_CNTL::Register>,           // 0x60
+    MU_STAT: ReadOnly<u32>,                               // 0x64
+    MU_BAUD: ReadWrite<u32, MU_BAUD::Register>,           // 0x68
+}
+
+/// The pair of GPIO pins to route the mini UART's TXD and RXD to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MiniUartPins {
+    /// TXD on GPIO 14 and RXD on GPIO 15. These are UART0's pins, so UART0 is
+    /// disconnected while the mini UART uses them.
+    Gpio14And15,
+    /// TXD on GPIO 32 and RXD on GPIO 33
+    Gpio32And33,
+    /// TXD on GPIO 40 and RXD on GPIO 41
+    Gpio40And41,
+}
+
+impl Default for MiniUartPins {
+    /// Pins that UART0 doesn't use, so that both can run at once
+    fn default() -> MiniUartPins {
+        MiniUartPins::Gpio32And33
+    }
+}
+
+impl MiniUart {
+    /// Initialise the mini UART at 115200 baud 8N1, on the default pins
+    pub fn init(&self) -> Result<BaudRate> {
+        self.init_with_baud_rate(115_200, MiniUartPins::default())
+    }
+
+    /// Initialise the mini UART with the given baud rate on the given pins,
+    /// and return the baud rate that was actually achieved. The mini UART
+    /// only supports 8N1 (or 7N1), so the baud rate is the only setting.
+    ///
+    /// The baud rate is derived from the VPU core clock, so it will drift if
+    /// the firmware changes the core frequency. Set `core_freq` (or
+    /// `enable_uart=1`) in config.txt to keep it fixed.
+    pub fn init_with_baud_rate(&self, baud_rate: u32, pins: MiniUartPins) -> Result<BaudRate> {
+        // Let any pending output finish. The AUX enable register is shared
+        // with the SPI peripherals, so only touch our bit.
+        if self.AUX_ENABLES.is_set(AUX_ENABLES::MINI_UART) {
+            while !self.MU_LSR.is_set(MU_LSR::TX_IDLE) {
+                spin_loop();
+            }
+        }
+        self.AUX_ENABLES.modify(AUX_ENABLES::MINI_UART::Enabled);
+
+        // Disable the transmitter and receiver while we configure it
+        self.MU_CNTL.set(0);
+
+        let clock_rate = match mailbox::get_clock_rate(mailbox::Clock::Core) {
+            Ok(rate) => rate,
+            Err(e) => return Err(UartError::MailboxError(e)),
+        };
+        let (divisor, baud_rate) = baud_rate::mini_uart_divisor(clock_rate, baud_rate)?;
+
+        // No interrupts, no modem control, and empty FIFOs
+        self.MU_IER.set(0);
+        self.MU_MCR.set(0);
+        self.MU_IIR.write(MU_IIR::FIFO_CLEAR::All);
+        self.MU_LCR.write(MU_LCR::DATA_SIZE::EightBit);
+        self.MU_BAUD.write(MU_BAUD::BAUD.val(divisor));
+
+        configure_pins(pins);
+
+        self.MU_CNTL.write(MU_CNTL::TX_ENABLE::Enabled + MU_CNTL::RX_ENABLE::Enabled);
+
+        Ok(baud_rate)
+    }
+
+    /// Write a single byte, waiting for space in the transmit FIFO
+    pub fn write_byte(&self, byte: u8) {
+        // Wait for the FIFO to have enough space
+        while !self.MU_LSR.is_set(MU_LSR::TX_EMPTY) {
+            spin_loop();
+        }
+
+        self.MU_IO.set(u32::from(byte));
+    }
+
+    /// Read a single byte, waiting for one to arrive
+    pub fn read_byte(&self) -> u8 {
+        // Wait for there to be a character available
+        while !self.MU_LSR.is_set(MU_LSR::DATA_READY) {
+            spin_loop();
+        }
+
+        self.MU_IO.get() as u8
+    }
+
+    /// Read a byte if one has arrived, without waiting
+    pub fn try_read_byte(&self) -> Option<u8> {
+        if self.MU_LSR.is_set(MU_LSR::DATA_READY) {
+            Some(self.MU_IO.get() as u8)
+        } else {
+            None
+        }
+    }
+
+    /// Wait until everything that has been written has been sent
+    pub fn flush(&self) {
+        while !self.MU_LSR.is_set(MU_LSR::TX_IDLE) {
+            spin_loop();
+        }
+    }
+}
+
+impl Console for MiniUart {
+    fn read_byte(&self) -> u8 {
+        MiniUart::read_byte(self)
+    }
+
+    fn try_read_byte(&self) -> Option<u8> {
+        MiniUart::try_read_byte(self)
+    }
+
+    fn write_byte(&self, byte: u8) {
+        MiniUart::write_byte(self, byte)
+    }
+
+    fn flush(&self) {
+        MiniUart::flush(self)
+    }
+}
+
+impl Write for MiniUart {
+    fn write_str(&mut self, s: &str) -> ::core::result::Result<(), ::core::fmt::Error> {
+        self.puts(s);
+        Ok(())
+    }
+}
@@ -11,6 +11,20 @@ register_bitfields! {
 
     /// GPIO Function Select 1
     GPFSEL1 [
+        /// Pin 17
+        FSEL17 OFFSET(21) NUMBITS(3) [
+            Input = 0b000,
+            Output = 0b001,
+            RTS0 = 0b111  // UART0     - Alternate function 3
+        ],
+
+        /// Pin 16
+        FSEL16 OFFSET(18) NUMBITS(3) [
+            Input = 0b000,
+            Output = 0b001,
+            CTS0 = 0b111  // UART0     - Alternate function 3
+        ],
+
         /// Pin 15
         FSEL15 OFFSET(15) NUMBITS(3) [
             Input = 0b000,
@@ -29,13 +43,57 @@ register_bitfields! {
         ]
     ],
 
-    /*GPFSEL2 [],
+    /*GPFSEL2 [],*/
 
-    GPFSEL3 [],
+    /// GPIO Function Select 3
+    GPFSEL3 [
+        /// Pin 33
+        FSEL33 OFFSET(9) NUMBITS(3) [
+            Input = 0b000,
+            Output = 0b001,
+            RXD1 = 0b010  // Mini UART - Alternate function 5
+        ],
 
-    GPFSEL4 [],
+        /// Pin 32
+        FSEL32 OFFSET(6) NUMBITS(3) [
+            Input = 0b000,
+            Output = 0b001,
+            TXD1 = 0b010  // Mini UART - Alternate function 5
+        ],
 
-    GPFSEL5 [],*/
+        /// Pin 31
+        FSEL31 OFFSET(3) NUMBITS(3) [
+            Input = 0b000,
+            Output = 0b001,
+            RTS0 = 0b111  // UART0     - Alternate function 3
+        ],
+
+        /// Pin 30
+        FSEL30 OFFSET(0) NUMBITS(3) [
+            Input = 0b000,
+            Output = 0b001,
+            CTS0 = 0b111  // UART0     - Alternate function 3
+        ]
+    ],
+
+    /// GPIO Function Select 4
+    GPFSEL4 [
+        /// Pin 41
+        FSEL41 OFFSET(3) NUMBITS(3) [
+            Input = 0b000,
+            Output = 0b001,
+            RXD1 = 0b010  // Mini UART - Alternate function 5
+        ],
+
+        /// Pin 40
+        FSEL40 OFFSET(0) NUMBITS(3) [
+            Input = 0b000,
+            Output = 0b001,
+            TXD1 = 0b010  // Mini UART - Alternate function 5
+        ]
+    ],
+
+    /*GPFSEL5 [],*/
 
     GPPUD [
         PUD OFFSET(0) NUMBITS(2) [
@@ -47,6 +105,30 @@ register_bitfields! {
 
     /// GPIO Pull-up/down Clock Register 0
     GPPUDCLK0 [
+        /// Pin 31
+        PUDCLK31 OFFSET(31) NUMBITS(1) [
+            NoEffect = 0,
+            AssertClock = 1
+        ],
+
+        /// Pin 30
+        PUDCLK30 OFFSET(30) NUMBITS(1) [
+            NoEffect = 0,
+            AssertClock = 1
+        ],
+
+        /// Pin 17
+        PUDCLK17 OFFSET(17) NUMBITS(1) [
+            NoEffect = 0,
+            AssertClock = 1
+        ],
+
+        /// Pin 16
+        PUDCLK16 OFFSET(16) NUMBITS(1) [
+            NoEffect = 0,
+            AssertClock = 1
+        ],
+
         /// Pin 15
         PUDCLK15 OFFSET(15) NUMBITS(1) [
             NoEffect = 0,
@@ -60,7 +142,25 @@ register_bitfields! {
         ]
     ],
     GPPUDCLK1 [
-        /// Pin 15
+        /// Pin 41
+        PUDCLK41 OFFSET(9) NUMBITS(1) [
+            NoEffect = 0,
+            AssertClock = 1
+        ],
+
+        /// Pin 40
+        PUDCLK40 OFFSET(8) NUMBITS(1) [
+            NoEffect = 0,
+            AssertClock = 1
+        ],
+
+        /// Pin 33
+        PUDCLK33 OFFSET(1) NUMBITS(1) [
+            NoEffect = 0,
+            AssertClock = 1
+        ],
+
+        /// Pin 32
         PUDCLK32 OFFSET(0) NUMBITS(1) [
             NoEffect = 0,
             AssertClock = 1
@@ -93,8 +193,8 @@ impl<T: IntLike, R: RegisterLongName> Deref for Reg<T, R> {
 pub const GPFSEL0: Reg<u32/*, GPFSEL0::Register*/> = unsafe { Reg::new((MMIO_BASE + 0x0020_0000) as *mut u32) };
 pub const GPFSEL1: Reg<u32, GPFSEL1::Register> = unsafe { Reg::new((MMIO_BASE + 0x0020_0004) as *mut u32) };
 pub const GPFSEL2: Reg<u32/*, GPFSEL2::Register*/> = unsafe { Reg::new((MMIO_BASE + 0x0020_0008) as *mut u32) };
-pub const GPFSEL3: Reg<u32/*, GPFSEL3::Register*/> = unsafe { Reg::new((MMIO_BASE + 0x0020_000C) as *mut u32) };
-pub const GPFSEL4: Reg<u32/*, GPFSEL4::Register*/> = unsafe { Reg::new((MMIO_BASE + 0x0020_0010) as *mut u32) };
+pub const GPFSEL3: Reg<u32, GPFSEL3::Register> = unsafe { Reg::new((MMIO_BASE + 0x0020_000C) as *mut u32) };
+pub const GPFSEL4: Reg<u32, GPFSEL4::Register> = unsafe { Reg::new((MMIO_BASE + 0x0020_0010) as *mut u32) };
 pub const GPFSEL5: Reg<u32/*, GPFSEL5::Register*/> = unsafe { Reg::new((MMIO_BASE + 0x0020_0014) as *mut u32) };
 
 pub const GPPUD: Reg<u32, GPPUD::Register> = unsafe { Reg::new((MMIO_BASE + 0x0020_0094) as *mut u32) };
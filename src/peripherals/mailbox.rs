@@ -87,13 +87,40 @@ pub enum Tag {
 
     SetPowerState = 0x0002_8001,
 
+    GetClockRate = 0x0003_0002,
     SetClockRate = 0x0003_8002,
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, IterableEnum)]
 #[repr(u32)]
 pub enum Clock {
+    EMMC = 0x0000_0001,
     UART = 0x0000_0002,
+    ARM = 0x0000_0003,
+    Core = 0x0000_0004,
+    V3D = 0x0000_0005,
+    H264 = 0x0000_0006,
+    ISP = 0x0000_0007,
+    SDRAM = 0x0000_0008,
+    Pixel = 0x0000_0009,
+    PWM = 0x0000_000A,
+}
+
+impl Clock {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Clock::EMMC => "emmc",
+            Clock::UART => "uart",
+            Clock::ARM => "arm",
+            Clock::Core => "core",
+            Clock::V3D => "v3d",
+            Clock::H264 => "h264",
+            Clock::ISP => "isp",
+            Clock::SDRAM => "sdram",
+            Clock::Pixel => "pixel",
+            Clock::PWM => "pwm",
+        }
+    }
 }
 
 #[derive(IterableEnum)]
@@ -272,6 +299,12 @@ pub fn set_power_state(device: Device, state: bool, wait_for_completion: bool) -
     Ok(message.get_response()[1] & 0x1 == 1)
 }
 
+pub fn get_clock_rate(clock: Clock) -> Result<u32> {
+    let mut message = Message::new();
+    message.send(Tag::GetClockRate, &[clock as u32], 8)?;
+    Ok(message.get_response()[1])
+}
+
 pub fn set_clock_rate(clock: Clock, rate: u32, skip_setting_turbo: u32) -> Result<u32> {
     let mut message = Message::new();
     message.send(Tag::SetClockRate, &[clock as u32, rate, skip_setting_turbo], 8)?;
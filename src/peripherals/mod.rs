@@ -1,3 +1,4 @@
+pub mod aux_uart;
 pub mod gpio;
 pub mod mailbox;
 pub mod power;
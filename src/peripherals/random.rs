@@ -1,6 +1,7 @@
 use crate::peripherals::MMIO_BASE;
 use register::{mmio::*, register_bitfields};
 use core::hint::spin_loop;
+use utils::sync::Once;
 
 // It starts with limited entropy, so query it a bunch of times to begin with
 const WARMUP_COUNT: u32 = 0x4_0000;
@@ -34,7 +35,7 @@ pub struct Rng {
 }
 
 impl Rng {
-    pub fn init(&self) {
+    fn init(&self) {
         // Turn off interrupts from the RNG
         self.INT_MASK.write(INT_MASK::INT_OFF::True);
 
@@ -72,8 +73,13 @@ impl Rng {
     }
 }
 
+static RNG_INIT: Once = Once::new();
+
+/// Get the hardware RNG, initialising it the first time that it's requested
 pub fn get_rng() -> &'static Rng {
-    unsafe {
+    let rng = unsafe {
         &*((MMIO_BASE + 0x0010_4000) as *const Rng)
-    }
+    };
+    RNG_INIT.call_once(|| rng.init());
+    rng
 }
@@ -2,9 +2,13 @@ use crate::peripherals::MMIO_BASE;
 use crate::peripherals::timer::sleep_cycles;
 use crate::peripherals::gpio;
 use crate::peripherals::mailbox;
+use crate::io::Console;
 use core::hint::spin_loop;
 use core::fmt::Write;
 use register::{mmio::*, register_bitfields};
+use utils::baud_rate::{self, UnachievableBaudRate};
+
+pub use utils::baud_rate::BaudRate;
 
 // PL011 UART registers.
 //
@@ -27,7 +31,13 @@ register_bitfields! {
         /// FIFO is disabled, this bit is set when the receive holding
         /// register is empty. If the FIFO is enabled, the RXFE bit is
         /// set when the receive FIFO is empty.
-        RXFE OFFSET(4) NUMBITS(1) []
+        RXFE OFFSET(4) NUMBITS(1) [],
+
+        /// UART busy. If this bit is set to 1, the UART is busy
+        /// transmitting data. This bit remains set until the complete
+        /// byte, including all the stop bits, has been sent from the
+        /// shift register.
+        BUSY OFFSET(3) NUMBITS(1) []
     ],
 
     /// Integer Baud rate divisor
@@ -51,11 +61,57 @@ register_bitfields! {
             SixBit = 0b01,
             SevenBit = 0b10,
             EightBit = 0b11
+        ],
+
+        /// Enable FIFOs. If this bit is set to 0, the FIFOs are
+        /// disabled (character mode), and the FIFOs become 1-byte-deep
+        /// holding registers.
+        FEN OFFSET(4) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
+        /// Two stop bits select. If this bit is set to 1, two stop bits
+        /// are transmitted at the end of the frame.
+        STP2 OFFSET(3) NUMBITS(1) [
+            One = 0,
+            Two = 1
+        ],
+
+        /// Even parity select. Controls the type of parity the UART
+        /// uses during transmission and reception. Has no effect when
+        /// parity is disabled by PEN.
+        EPS OFFSET(2) NUMBITS(1) [
+            Odd = 0,
+            Even = 1
+        ],
+
+        /// Parity enable
+        PEN OFFSET(1) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
         ]
     ],
 
     /// Control Register
     CR [
+        /// CTS hardware flow control enable. If this bit is set to 1,
+        /// CTS hardware flow control is enabled. Data is only
+        /// transmitted when the nUARTCTS signal is asserted.
+        CTSEN  OFFSET(15) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
+        /// RTS hardware flow control enable. If this bit is set to 1,
+        /// RTS hardware flow control is enabled. Data is only requested
+        /// when there is space in the receive FIFO for it to be
+        /// received.
+        RTSEN  OFFSET(14) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
         /// Receive enable. If this bit is set to 1, the receive
         /// section of the UART is enabled. Data reception occurs for
         /// UART signals. When the UART is disabled in the middle of
@@ -99,12 +155,117 @@ pub fn get_uart() -> &'static Uart {
     }
 }
 
+/// The rate to request for the UART reference clock. The baud rate divisor
+/// is 16 times the clock period, so this is enough for 921600 baud with a
+/// small enough error for the fractional divisor to absorb.
+const UART_CLOCK_RATE: u32 = 48_000_000;
+
 #[derive(Debug)]
 pub enum UartError {
     MailboxError(mailbox::MailboxError),
+    /// The requested baud rate can't be produced from the given UART clock
+    UnachievableBaudRate { baud_rate: u32, clock_rate: u32 },
 }
 pub type Result<T> = ::core::result::Result<T, UartError>;
 
+impl From<UnachievableBaudRate> for UartError {
+    fn from(e: UnachievableBaudRate) -> UartError {
+        UartError::UnachievableBaudRate { baud_rate: e.baud_rate, clock_rate: e.clock_rate }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataBits {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// The pair of GPIO pins to route the UART0 RTS/CTS signals to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowControlPins {
+    /// CTS on GPIO 16 and RTS on GPIO 17
+    Gpio16And17,
+    /// CTS on GPIO 30 and RTS on GPIO 31
+    Gpio30And31,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowControl {
+    None,
+    /// RTS/CTS hardware flow control. The host's adapter needs to support
+    /// it too, otherwise CTS will never be asserted and nothing will be sent.
+    RtsCts(FlowControlPins),
+}
+
+/// The line settings to configure the UART with
+#[derive(Clone, Copy, Debug)]
+pub struct UartConfig {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub fifo_enabled: bool,
+    pub flow_control: FlowControl,
+}
+
+impl Default for UartConfig {
+    /// 115200 baud, 8N1, with the FIFOs enabled and no flow control
+    fn default() -> UartConfig {
+        UartConfig {
+            baud_rate: 115_200,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            fifo_enabled: true,
+            flow_control: FlowControl::None,
+        }
+    }
+}
+
+/// Route the RTS and CTS signals to the given pins, with the pull up/down
+/// resistors disabled
+fn configure_flow_control_pins(pins: FlowControlPins) {
+    match pins {
+        FlowControlPins::Gpio16And17 => {
+            gpio::GPFSEL1.modify(gpio::GPFSEL1::FSEL16::CTS0 + gpio::GPFSEL1::FSEL17::RTS0);
+        },
+        FlowControlPins::Gpio30And31 => {
+            gpio::GPFSEL3.modify(gpio::GPFSEL3::FSEL30::CTS0 + gpio::GPFSEL3::FSEL31::RTS0);
+        },
+    }
+    gpio::GPPUD.set(0);
+
+    sleep_cycles(150);
+
+    match pins {
+        FlowControlPins::Gpio16And17 => gpio::GPPUDCLK0.write(
+            gpio::GPPUDCLK0::PUDCLK16::AssertClock + gpio::GPPUDCLK0::PUDCLK17::AssertClock
+        ),
+        FlowControlPins::Gpio30And31 => gpio::GPPUDCLK0.write(
+            gpio::GPPUDCLK0::PUDCLK30::AssertClock + gpio::GPPUDCLK0::PUDCLK31::AssertClock
+        ),
+    }
+
+    sleep_cycles(150);
+
+    gpio::GPPUDCLK0.set(0);
+}
+
 #[allow(non_snake_case)]
 #[repr(C)]
 pub struct Uart {
@@ -115,20 +276,41 @@ pub struct Uart {
     IBRD: WriteOnly<u32, IBRD::Register>, // 0x24
     FBRD: WriteOnly<u32, FBRD::Register>, // 0x28
     LCRH: WriteOnly<u32, LCRH::Register>, // 0x2C
-    CR: WriteOnly<u32, CR::Register>,     // 0x30
+    CR: ReadWrite<u32, CR::Register>,     // 0x30
     __reserved_2: [u32; 4],               // 0x34
     ICR: WriteOnly<u32, ICR::Register>,   // 0x44
 }
 
 impl Uart {
-    pub fn init(&self) -> Result<()> {
-        // Turn off the UART so we can configure it
-        self.CR.set(0);
+    /// Initialise the UART with the default settings (115200 baud 8N1)
+    pub fn init(&self) -> Result<BaudRate> {
+        self.init_with_config(&UartConfig::default())
+    }
 
-        // Set the UART clock speed
-        if let Err(e) = mailbox::set_clock_rate(mailbox::Clock::UART, 4_000_000, 0) {
-            return Err(UartError::MailboxError(e));
+    /// Whether the UART has been enabled, by us or by the firmware
+    pub fn is_enabled(&self) -> bool {
+        self.CR.is_set(CR::UARTEN)
+    }
+
+    /// Initialise the UART with the given line settings, and return the baud
+    /// rate that was actually achieved
+    pub fn init_with_config(&self, config: &UartConfig) -> Result<BaudRate> {
+        // Let any pending output finish, then turn off the UART so we can
+        // configure it
+        while self.FR.is_set(FR::BUSY) {
+            spin_loop();
         }
+        self.CR.set(0);
+        // Flush the transmit FIFO
+        self.LCRH.set(0);
+
+        // Set the UART clock speed. The firmware may not give us exactly
+        // what we asked for, so the divisors are based on what it reports.
+        let clock_rate = match mailbox::set_clock_rate(mailbox::Clock::UART, UART_CLOCK_RATE, 0) {
+            Ok(rate) => rate,
+            Err(e) => return Err(UartError::MailboxError(e)),
+        };
+        let (ibrd, fbrd, baud_rate) = baud_rate::pl011_divisors(clock_rate, config.baud_rate)?;
 
         gpio::GPFSEL1.modify(gpio::GPFSEL1::FSEL14::TXD0 + gpio::GPFSEL1::FSEL15::RXD0);
         gpio::GPPUD.set(0);
@@ -143,46 +325,77 @@ impl Uart {
 
         gpio::GPPUDCLK0.set(0);
 
-        // Set the baud rate to 11520 baud
+        if let FlowControl::RtsCts(pins) = config.flow_control {
+            configure_flow_control_pins(pins);
+        }
+
+        // Set the baud rate and line settings. LCRH must be written after
+        // the divisors for them to take effect.
         self.ICR.write(ICR::ALL::CLEAR);
-        self.IBRD.set(2);
-        self.FBRD.set(11);
-        self.LCRH.write(LCRH::WLEN::EightBit);
-        self.CR.write(CR::UARTEN::Enabled + CR::TXE::Enabled + CR::RXE::Enabled);
+        self.IBRD.set(ibrd);
+        self.FBRD.set(fbrd);
+        self.LCRH.write(
+            match config.data_bits {
+                DataBits::Five => LCRH::WLEN::FiveBit,
+                DataBits::Six => LCRH::WLEN::SixBit,
+                DataBits::Seven => LCRH::WLEN::SevenBit,
+                DataBits::Eight => LCRH::WLEN::EightBit,
+            }
+            + match config.parity {
+                Parity::None => LCRH::PEN::Disabled,
+                Parity::Odd => LCRH::PEN::Enabled + LCRH::EPS::Odd,
+                Parity::Even => LCRH::PEN::Enabled + LCRH::EPS::Even,
+            }
+            + match config.stop_bits {
+                StopBits::One => LCRH::STP2::One,
+                StopBits::Two => LCRH::STP2::Two,
+            }
+            + if config.fifo_enabled { LCRH::FEN::Enabled } else { LCRH::FEN::Disabled }
+        );
+        self.CR.write(
+            CR::UARTEN::Enabled + CR::TXE::Enabled + CR::RXE::Enabled
+            + match config.flow_control {
+                FlowControl::None => CR::RTSEN::Disabled + CR::CTSEN::Disabled,
+                FlowControl::RtsCts(_) => CR::RTSEN::Enabled + CR::CTSEN::Enabled,
+            }
+        );
 
-        Ok(())
+        Ok(baud_rate)
     }
 
-    pub fn send(&self, c: char) {
+    /// Write a single byte, waiting for space in the transmit FIFO
+    pub fn write_byte(&self, byte: u8) {
         // Wait for the buffer to have enough space
         while self.FR.is_set(FR::TXFF) {
             spin_loop();
         }
 
-        self.DR.set(c as u32);
+        self.DR.set(u32::from(byte));
     }
 
-    pub fn puts(&self, string: &str) {
-        for c in string.chars() {
-            if c == '\n' {
-                self.send('\r');
-            }
-            self.send(c);
-        }
-    }
-
-    pub fn getc(&self) -> char {
+    /// Read a single byte, waiting for one to arrive
+    pub fn read_byte(&self) -> u8 {
         // Wait for there to be a character available
         while self.FR.is_set(FR::RXFE) {
             spin_loop();
         }
 
-        let ret = self.DR.get() as u8 as char;
+        self.DR.get() as u8
+    }
 
-        if ret == '\r' {
-            '\n'
+    /// Read a byte if one has arrived, without waiting
+    pub fn try_read_byte(&self) -> Option<u8> {
+        if self.FR.is_set(FR::RXFE) {
+            None
         } else {
-            ret
+            Some(self.DR.get() as u8)
+        }
+    }
+
+    /// Wait until everything that has been written has been sent
+    pub fn flush(&self) {
+        while self.FR.is_set(FR::BUSY) {
+            spin_loop();
         }
     }
 
@@ -237,3 +450,21 @@ impl Write for Uart {
         Ok(())
     }
 }
+
+impl Console for Uart {
+    fn read_byte(&self) -> u8 {
+        Uart::read_byte(self)
+    }
+
+    fn try_read_byte(&self) -> Option<u8> {
+        Uart::try_read_byte(self)
+    }
+
+    fn write_byte(&self, byte: u8) {
+        Uart::write_byte(self, byte)
+    }
+
+    fn flush(&self) {
+        Uart::flush(self)
+    }
+}
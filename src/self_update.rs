@@ -1,57 +1,129 @@
-use crate::peripherals::uart0::Uart;
+use crate::io::Console;
 use crate::peripherals::mailbox;
+use crate::peripherals::timer;
+use core::convert::TryInto;
 use core::ptr;
+use core::slice;
 use core::sync::atomic::{compiler_fence, Ordering};
 use core::mem::transmute;
+use utils::block_transfer::{Receiver, Step, TransferError, ACK, CANCEL, NAK};
+use utils::crc;
+use utils::signed_image::{self, ImageError, Policy, KEY_LEN};
 
 extern "C" {
     static __self_update_code_start: usize;
     static __self_update_code_end: usize;
-    static __program_end: usize;
+    fn get_stack_ptr() -> usize;
 }
 
+/// Where the kernel is loaded, and so where the new image is copied to
+const LOAD_ADDRESS: usize = 0x80_000;
+
+// The host sends ^ followed by the size and CRC-32 of the image, as little
+// endian u32s. If the kernel replies with READY, the image is sent in blocks
+// (see utils::block_transfer). Once they have all arrived, the kernel replies
+// with ACK if the image is intact, before starting it, NAK if it isn't, or
+// UNTRUSTED if it won't run it (see utils::signed_image), and carries on
+// running. CANCEL is only sent if the transfer itself fails.
+/// Ready to receive the image in blocks
+const READY: u8 = 0x12;
+/// The image can't be accepted
+const REJECTED: u8 = 0x18;
+/// The image arrived intact, but isn't signed with the right key
+const UNTRUSTED: u8 = 0x1A;
+
+/// How long to wait for each byte of the image, in microseconds, before
+/// assuming that the host has stopped sending
+const BYTE_TIMEOUT: u64 = 1_000_000;
+/// How long the whole transfer can take, in microseconds
+const TRANSFER_TIMEOUT: u64 = 300_000_000;
+
+// Filled in by build.rs with the key that images have to be signed with, or
+// empty if there isn't one
+const PUBLIC_KEY: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/public_key.bin"));
+
+#[cfg(feature = "unsigned-updates")]
+const POLICY: Policy = Policy::AllowUnsigned;
+#[cfg(not(feature = "unsigned-updates"))]
+const POLICY: Policy = Policy::SignedOnly;
+
 #[derive(Debug)]
 pub enum UpdateError {
     MailboxError(mailbox::MailboxError),
     SizeError,
+    /// The image didn't arrive in time
+    Timeout,
+    /// The image couldn't be received
+    TransferError(TransferError),
+    /// The image that arrived doesn't match the CRC that the host sent
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// The image isn't allowed to run
+    ImageError(ImageError),
 }
 
+/// Receive a new kernel from the host over `uart` (normally the console, which
+/// the host has just sent ^ on), and run it
 #[cfg(target_arch = "aarch64")]
-pub fn self_update(uart:  &Uart) -> Result<!, UpdateError> {
+pub fn self_update(uart: &dyn Console) -> Result<!, UpdateError> {
     let self_update_code_start: usize = unsafe { &__self_update_code_start as *const usize as usize };
     let self_update_code_end: usize = unsafe { &__self_update_code_end as *const usize as usize };
-    let program_end: usize = unsafe { &__program_end as *const usize as usize };
+    // Everything in use, including the stack, is below this
+    let stack_top: usize = unsafe { get_stack_ptr() };
 
     // To get here, the host should have notified us that it wants to update
-    // It will now be sending the size
-    let mut new_size: u32 = uart.getc() as u8 as u32;
-    new_size |= (uart.getc() as u8 as u32) << 8;
-    new_size |= (uart.getc() as u8 as u32) << 16;
-    new_size |= (uart.getc() as u8 as u32) << 24;
-
-    let new_size = new_size as usize;
+    // It will now be sending the size and checksum
+    let mut header = [0u8; 8];
+    uart.read_exact(&mut header);
+    let new_size = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let expected_crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
 
     // Query the GPU to find out how much RAM we have
     let (_base, available_memory) = match mailbox::get_memory_range() {
         Ok(r) => r,
         Err(e) => {
-            uart.send(0x18 as char);
+            uart.write_byte(REJECTED);
             return Err(UpdateError::MailboxError(e))
         }
     };
 
     let self_update_code_len = self_update_code_end - self_update_code_start;
 
-    if new_size > available_memory as usize - 0x80_000 - self_update_code_len {
+    // The image is received into a staging area, so that it can be checked
+    // before anything is overwritten. That can't overlap the running kernel,
+    // or where the image will be copied to. The copier goes after it.
+    let staging = align_up(max(stack_top, LOAD_ADDRESS + new_size));
+    let new_self_update_loc = align_up(staging + new_size);
+
+    if new_self_update_loc + self_update_code_len > available_memory as usize {
         // Not enough RAM
-        uart.send(0x18 as char);
+        uart.write_byte(REJECTED);
         return Err(UpdateError::SizeError);
     }
 
-    unsafe {
-        // We have space for it, so now we need to relocate the self update assembly
-        let new_self_update_loc = max(new_size + 0x80_000, program_end);
+    // We're ready to receive it - let the host know
+    uart.write_byte(READY);
+
+    let image = unsafe { slice::from_raw_parts_mut(staging as *mut u8, new_size) };
+    receive(uart, image)?;
+
+    let actual_crc = crc::crc32(image);
+    if actual_crc != expected_crc {
+        uart.write_byte(NAK);
+        return Err(UpdateError::ChecksumMismatch { expected: expected_crc, actual: actual_crc });
+    }
 
+    // Checking the signature takes a few KiB of stack, which get_stack_ptr
+    // in boot_cores.S leaves room for
+    let kernel = match signed_image::verify(image, public_key(), POLICY) {
+        Ok(verified) => verified.kernel,
+        Err(e) => {
+            uart.write_byte(UNTRUSTED);
+            return Err(UpdateError::ImageError(e));
+        }
+    };
+
+    unsafe {
+        // It's intact and trusted, so now we need to relocate the self update assembly
         ptr::copy_nonoverlapping(self_update_code_start as *const u8, new_self_update_loc as *mut u8, self_update_code_len);
 
         compiler_fence(Ordering::SeqCst);
@@ -60,22 +132,72 @@ pub fn self_update(uart:  &Uart) -> Result<!, UpdateError> {
         asm!("IC IALLU
               ISB");
 
-        // We're ready to receive it - let the host know
-        uart.send(0x12 as char);
+        // Make sure the host hears about it before the new kernel resets the
+        // UART
+        uart.write_byte(ACK);
+        uart.flush();
 
         // Now construct a pointer to the new function
-        let self_update_fn: extern "C" fn (usize, usize, &Uart) -> ! = transmute(new_self_update_loc);
-        // (the signature is (start_address, length, uart_addr))
+        let self_update_fn: extern "C" fn (usize, usize, usize) -> ! = transmute(new_self_update_loc);
+        // (the signature is (destination, source, length))
         // finally, call it
-        self_update_fn(0x80_000, new_size as usize, uart)
+        self_update_fn(LOAD_ADDRESS, kernel.as_ptr() as usize, kernel.len())
     }
 }
 
 #[cfg(not(target_arch = "aarch64"))]
-pub fn self_update(uart:  &Uart) -> Result<!, UpdateError> {
+pub fn self_update(uart: &dyn Console) -> Result<!, UpdateError> {
     unimplemented!();
 }
 
+/// The key that images have to be signed with, if there is one
+fn public_key() -> Option<&'static [u8; KEY_LEN]> {
+    PUBLIC_KEY.try_into().ok()
+}
+
+/// Receive the image from the host, one block at a time
+fn receive(uart: &dyn Console, image: &mut [u8]) -> Result<(), UpdateError> {
+    let timer = timer::get_timer();
+    let deadline = timer.read_timer() + TRANSFER_TIMEOUT;
+    let mut receiver = Receiver::new(image);
+    loop {
+        let step = match read_byte_timeout(uart, timer) {
+            Some(byte) => receiver.feed(byte),
+            None => receiver.timeout(),
+        };
+        match step {
+            Step::Continue => (),
+            Step::Reply(reply) => uart.write_byte(reply),
+            Step::Finished => return Ok(()),
+            Step::Failed(e) => {
+                // There's no need to tell the host if it was the one that
+                // gave up
+                if e != TransferError::Cancelled {
+                    uart.write_byte(CANCEL);
+                }
+                return Err(UpdateError::TransferError(e));
+            }
+        }
+        if timer.read_timer() >= deadline {
+            uart.write_byte(CANCEL);
+            return Err(UpdateError::Timeout);
+        }
+    }
+}
+
+/// Read a byte from the host, or give up if it doesn't arrive in time
+fn read_byte_timeout(uart: &dyn Console, timer: &timer::SystemTimer) -> Option<u8> {
+    let deadline = timer.read_timer() + BYTE_TIMEOUT;
+    loop {
+        if let Some(byte) = uart.try_read_byte() {
+            return Some(byte);
+        }
+        if timer.read_timer() >= deadline {
+            return None;
+        }
+    }
+}
+
 fn max(a: usize, b: usize) -> usize {
     if a > b {
         a
@@ -84,5 +206,10 @@ fn max(a: usize, b: usize) -> usize {
     }
 }
 
+/// Round up to a multiple of 16 bytes
+fn align_up(address: usize) -> usize {
+    (address + 15) & !15
+}
+
 #[cfg(target_arch = "aarch64")]
-global_asm!(include_str!("self_update.S"));
\ No newline at end of file
+global_asm!(include_str!("self_update.S"));
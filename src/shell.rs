@@ -0,0 +1,157 @@
+mod builtins;
+
+use crate::io;
+use crate::println;
+use crate::self_update;
+use utils::line_editor::{Event, LineEditor};
+use utils::sync::Mutex;
+use utils::utf8::Utf8Reader;
+
+/// The longest line that can be entered at the prompt
+const MAX_LINE_LENGTH: usize = 256;
+/// The number of previous lines to remember
+const HISTORY_LENGTH: usize = 16;
+/// The maximum number of words in a command, including the command name
+const MAX_ARGS: usize = 16;
+/// The maximum number of commands that can be registered
+const MAX_COMMANDS: usize = 32;
+
+const PROMPT: &str = "> ";
+
+// The history makes the editor several KiB, which is too big for the stack
+static EDITOR: Mutex<LineEditor<MAX_LINE_LENGTH, HISTORY_LENGTH>> = Mutex::new(LineEditor::new(PROMPT));
+
+/// A command that can be run from the shell
+pub struct Command {
+    /// The name that the command is run with
+    pub name: &'static str,
+    /// The arguments that the command takes, shown in the help
+    pub usage: &'static str,
+    /// A one line description of the command
+    pub help: &'static str,
+    /// The function that runs the command. `args[0]` is the command name.
+    pub run: fn(args: &[&str]) -> Result<()>,
+}
+
+#[derive(Debug)]
+pub enum ShellError {
+    /// The command was given the wrong arguments
+    Usage,
+    /// There is no space left to register any more commands
+    TooManyCommands,
+    /// A command with the same name is already registered
+    DuplicateCommand(&'static str),
+    /// There is no console to read commands from
+    NoConsole,
+}
+pub type Result<T> = ::core::result::Result<T, ShellError>;
+
+struct Registry {
+    commands: [Option<&'static Command>; MAX_COMMANDS],
+}
+
+static COMMANDS: Mutex<Registry> = Mutex::new(Registry {
+    commands: [None; MAX_COMMANDS],
+});
+
+/// Register a command so that it can be run from the shell
+pub fn register(command: &'static Command) -> Result<()> {
+    let mut registry = COMMANDS.lock();
+    if registry.commands.iter().flatten().any(|c| c.name == command.name) {
+        return Err(ShellError::DuplicateCommand(command.name));
+    }
+    match registry.commands.iter_mut().find(|c| c.is_none()) {
+        Some(slot) => {
+            *slot = Some(command);
+            Ok(())
+        },
+        None => Err(ShellError::TooManyCommands),
+    }
+}
+
+/// Find the registered command with the given name
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.lock().commands.iter().flatten().find(|c| c.name == name).copied()
+}
+
+/// Call `f` with each registered command, in the order they were registered
+pub fn for_each_command<F: FnMut(&'static Command)>(mut f: F) {
+    let registry = COMMANDS.lock();
+    for command in registry.commands.iter().flatten() {
+        f(command);
+    }
+}
+
+/// Run the shell on the console, forever
+pub fn run() -> Result<!> {
+    let console = io::console().ok_or(ShellError::NoConsole)?;
+    builtins::register_all()?;
+
+    let mut editor = EDITOR.lock();
+    let mut input = Utf8Reader::new(|| console.read_byte());
+    let mut stdout = io::Stdout;
+    let _ = editor.start(&mut stdout);
+    loop {
+        let c = input.read_char();
+        // The host starts an update by sending ^ followed by the image size,
+        // so handle it straight away rather than waiting for a line
+        if c == '^' && editor.is_empty() {
+            if let Err(e) = self_update::self_update(console) {
+                println!("{:?}", e);
+            }
+            let _ = editor.start(&mut stdout);
+            continue;
+        }
+
+        match editor.feed(c, &mut stdout, &complete_command) {
+            Ok(Some(Event::Line)) => {
+                execute(editor.line());
+                let _ = editor.start(&mut stdout);
+            },
+            Ok(Some(Event::Interrupt)) => {
+                let _ = editor.start(&mut stdout);
+            },
+            Ok(None) | Err(_) => (),
+        }
+    }
+}
+
+/// Complete the names of commands, which are the first word on the line
+fn complete_command(line: &str, candidate: &mut dyn FnMut(&str)) {
+    if line.contains(' ') {
+        return;
+    }
+    for_each_command(|command| {
+        if command.name.starts_with(line) {
+            candidate(command.name);
+        }
+    });
+}
+
+/// Parse a line and run the command that it names
+pub fn execute(line: &str) {
+    let mut args: [&str; MAX_ARGS] = [""; MAX_ARGS];
+    let mut argc = 0;
+    for word in line.split_whitespace() {
+        if argc == MAX_ARGS {
+            println!("Too many arguments (the limit is {})", MAX_ARGS - 1);
+            return;
+        }
+        args[argc] = word;
+        argc += 1;
+    }
+    if argc == 0 {
+        return;
+    }
+
+    // The registry lock is released before running the command, so that
+    // commands can look at the registry themselves
+    match find(args[0]) {
+        None => println!("Unknown command {}. Try help.", args[0]),
+        Some(command) => match (command.run)(&args[..argc]) {
+            Ok(()) => (),
+            Err(ShellError::Usage) => println!("Usage: {} {}", command.name, command.usage),
+            Err(e) => println!("{:?}", e),
+        },
+    }
+}
@@ -0,0 +1,248 @@
+use super::{Command, Result, ShellError};
+use crate::display::text_console;
+use crate::log;
+use crate::peripherals::{aux_uart, mailbox, power, random, timer, uart0};
+use crate::crash_record;
+use crate::gdb;
+use crate::io::{self, Console};
+use crate::self_update;
+use crate::symbols;
+use crate::{print, println};
+use utils::sync::Mutex;
+
+/// The mini UART's sink, while `miniuart on` is copying output to it
+static MINI_UART_SINK: Mutex<Option<io::SinkId>> = Mutex::new(None);
+
+static BUILTINS: [Command; 16] = [
+    Command { name: "help", usage: "[command]", help: "List the commands, or show the usage of one", run: help },
+    Command { name: "mac", usage: "", help: "Show the MAC address", run: mac },
+    Command { name: "serial", usage: "", help: "Show the board serial number", run: serial },
+    Command { name: "mem", usage: "", help: "Show the memory available to the ARM", run: mem },
+    Command { name: "rand", usage: "[count]", help: "Generate random numbers", run: rand },
+    Command { name: "reboot", usage: "", help: "Reboot the board", run: reboot },
+    Command { name: "shutdown", usage: "", help: "Power off the board", run: shutdown },
+    Command { name: "update", usage: "", help: "Receive a new kernel over the console (the host sends the size next)", run: update },
+    Command { name: "uptime", usage: "", help: "Show the time since boot", run: uptime },
+    Command { name: "clock", usage: "[name]", help: "Show the clock rates", run: clock },
+    Command { name: "dmesg", usage: "", help: "Show the kernel log", run: dmesg },
+    Command { name: "addr2sym", usage: "<address>", help: "Show the function containing a hex address", run: addr2sym },
+    Command { name: "lastcrash", usage: "", help: "Show the panic that ended the previous boot", run: lastcrash },
+    Command { name: "gdb", usage: "[uart0|mini]", help: "Stop and wait for GDB to attach over a UART (the console by default)", run: gdb },
+    Command { name: "fb", usage: "[width height]", help: "Allocate a frame buffer and draw a test pattern", run: fb },
+    Command { name: "miniuart", usage: "<on|off>", help: "Start or stop copying the console output to the mini UART", run: miniuart },
+];
+
+pub fn register_all() -> Result<()> {
+    for command in BUILTINS.iter() {
+        super::register(command)?;
+    }
+    Ok(())
+}
+
+fn help(args: &[&str]) -> Result<()> {
+    match args {
+        [_] => super::for_each_command(|command| {
+            println!("  {:<10} {}", command.name, command.help);
+        }),
+        [_, name] => match super::find(name) {
+            Some(command) => {
+                println!("{} {}", command.name, command.usage);
+                println!("  {}", command.help);
+            },
+            None => println!("Unknown command {}", name),
+        },
+        _ => return Err(ShellError::Usage),
+    }
+    Ok(())
+}
+
+fn mac(_args: &[&str]) -> Result<()> {
+    match mailbox::get_mac() {
+        Ok(mac) => println!("{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+        ),
+        Err(e) => println!("{:?}", e),
+    }
+    Ok(())
+}
+
+fn serial(_args: &[&str]) -> Result<()> {
+    match mailbox::get_serial() {
+        Ok(serial) => println!("{:X}", serial),
+        Err(e) => println!("{:?}", e),
+    }
+    Ok(())
+}
+
+fn mem(_args: &[&str]) -> Result<()> {
+    match mailbox::get_memory_range() {
+        Ok((base, length)) => println!("Memory size: {:#X}B. Base: {:#X}", length, base),
+        Err(e) => println!("{:?}", e),
+    }
+    Ok(())
+}
+
+fn rand(args: &[&str]) -> Result<()> {
+    let count = match args {
+        [_] => 1,
+        [_, count] => count.parse().map_err(|_| ShellError::Usage)?,
+        _ => return Err(ShellError::Usage),
+    };
+    let rng = random::get_rng();
+    for _ in 0..count {
+        println!("{:#x}", rng.rand());
+    }
+    Ok(())
+}
+
+fn reboot(_args: &[&str]) -> Result<()> {
+    power::get_power_manager().reboot();
+}
+
+fn shutdown(_args: &[&str]) -> Result<()> {
+    if let Err(e) = power::get_power_manager().shutdown() {
+        println!("{:?}", e);
+    }
+    Ok(())
+}
+
+fn update(_args: &[&str]) -> Result<()> {
+    let console = io::console().ok_or(ShellError::NoConsole)?;
+    if let Err(e) = self_update::self_update(console) {
+        println!("{:?}", e);
+    }
+    Ok(())
+}
+
+fn uptime(_args: &[&str]) -> Result<()> {
+    let us = timer::get_timer().read_timer();
+    let secs = us / 1_000_000;
+    println!("{}:{:02}:{:02}.{:06}", secs / 3600, secs / 60 % 60, secs % 60, us % 1_000_000);
+    Ok(())
+}
+
+fn clock(args: &[&str]) -> Result<()> {
+    let name = match args {
+        [_] => None,
+        [_, name] => Some(*name),
+        _ => return Err(ShellError::Usage),
+    };
+    let mut found = false;
+    for clock in mailbox::Clock::values() {
+        if name.map_or(true, |name| name.eq_ignore_ascii_case(clock.name())) {
+            found = true;
+            let clock_name = clock.name();
+            match mailbox::get_clock_rate(clock) {
+                Ok(rate) => println!("  {:<6} {} Hz", clock_name, rate),
+                Err(e) => println!("  {:<6} {:?}", clock_name, e),
+            }
+        }
+    }
+    if !found {
+        println!("Unknown clock {}", name.unwrap_or(""));
+    }
+    Ok(())
+}
+
+fn dmesg(_args: &[&str]) -> Result<()> {
+    log::print_buffer();
+    Ok(())
+}
+
+fn addr2sym(args: &[&str]) -> Result<()> {
+    let address = match args {
+        [_, address] => address.trim_start_matches("0x"),
+        _ => return Err(ShellError::Usage),
+    };
+    let address = u64::from_str_radix(address, 16).map_err(|_| ShellError::Usage)?;
+    match symbols::table() {
+        Some(table) => match table.lookup(address) {
+            Some((symbol, offset)) => println!("{}+{:#x}", symbol.name, offset),
+            None => println!("No function contains {:#x}", address),
+        },
+        None => println!("The kernel was built without a symbol table"),
+    }
+    Ok(())
+}
+
+fn lastcrash(_args: &[&str]) -> Result<()> {
+    crash_record::with_previous(|previous| match previous {
+        Some(previous) => {
+            print!("{}", previous.text());
+        },
+        None => println!("The previous boot didn't crash"),
+    });
+    Ok(())
+}
+
+fn gdb(args: &[&str]) -> Result<()> {
+    let console: &'static dyn Console = match args {
+        [_] => io::console().ok_or(ShellError::NoConsole)?,
+        [_, "uart0"] => uart0::get_uart(),
+        [_, "mini"] => {
+            let mini_uart = aux_uart::get_mini_uart();
+            if let Err(e) = mini_uart.init() {
+                println!("Failed to start the mini UART: {:?}", e);
+                return Ok(());
+            }
+            mini_uart
+        },
+        _ => return Err(ShellError::Usage),
+    };
+    if let Err(e) = gdb::attach(console) {
+        println!("Failed to find the memory that GDB can access: {:?}", e);
+        return Ok(());
+    }
+    println!("Waiting for GDB");
+    gdb::breakpoint();
+    Ok(())
+}
+
+fn fb(args: &[&str]) -> Result<()> {
+    let (width, height) = match args {
+        [_] => (1920, 1080),
+        [_, width, height] => (
+            width.parse().map_err(|_| ShellError::Usage)?,
+            height.parse().map_err(|_| ShellError::Usage)?,
+        ),
+        _ => return Err(ShellError::Usage),
+    };
+    // The text console owns the frame buffer, so it has to be the one to
+    // reallocate it, otherwise it would carry on drawing on the old one
+    let text_console = text_console::get_text_console();
+    if let Err(e) = text_console.init(width, height) {
+        println!("{:?}", e);
+        return Ok(());
+    }
+    text_console.with_frame_buffer(|frame_buffer| frame_buffer.draw());
+    Ok(())
+}
+
+fn miniuart(args: &[&str]) -> Result<()> {
+    let mut sink = MINI_UART_SINK.lock();
+    match (args, *sink) {
+        ([_, "on"], None) => {
+            let mini_uart = aux_uart::get_mini_uart();
+            match mini_uart.init() {
+                Ok(baud_rate) => println!("Mini UART running at {} baud", baud_rate.actual),
+                Err(e) => {
+                    println!("Failed to start the mini UART: {:?}", e);
+                    return Ok(());
+                },
+            }
+            match io::add_sink(mini_uart, log::Level::Trace) {
+                Ok(id) => *sink = Some(id),
+                Err(e) => println!("Failed to send output to the mini UART: {:?}", e),
+            }
+        },
+        ([_, "off"], Some(id)) => {
+            if let Err(e) = io::remove_sink(id) {
+                println!("Failed to stop sending output to the mini UART: {:?}", e);
+            }
+            *sink = None;
+        },
+        ([_, "on"], Some(_)) | ([_, "off"], None) => (),
+        _ => return Err(ShellError::Usage),
+    }
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use core::fmt;
+use core::slice;
+use utils::symbols::SymbolTable;
+
+extern "C" {
+    static __symbols_start: u8;
+    static __symbols_end: u8;
+}
+
+// Filled in by build.rs with the table that `symtab` made from the previous
+// build, or empty if there isn't one. This is only ever found through the
+// linker's symbols around it, so that the code doesn't depend on how long it
+// is, and comes out the same in both builds.
+#[link_section = ".symbols"]
+#[used]
+static TABLE: [u8; include_bytes!(concat!(env!("OUT_DIR"), "/symbols.bin")).len()] =
+    *include_bytes!(concat!(env!("OUT_DIR"), "/symbols.bin"));
+
+/// Get the table of the kernel's functions, if it was embedded in the image
+pub fn table() -> Option<SymbolTable<'static>> {
+    let bytes = unsafe {
+        let start = &__symbols_start as *const u8;
+        let end = &__symbols_end as *const u8;
+        slice::from_raw_parts(start, end as usize - start as usize)
+    };
+    SymbolTable::parse(bytes)
+}
+
+/// Shows an address along with the function that contains it, if it's known
+pub struct Located(pub u64);
+
+impl fmt::Display for Located {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#018x}", self.0)?;
+        if let Some((symbol, offset)) = table().and_then(|table| table.lookup(self.0)) {
+            write!(f, " {}+{:#x}", symbol.name, offset)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+//! Converts the output of `llvm-nm --defined-only --print-size --demangle`
+//! into the symbol table format in `utils::symbols`.
+//!
+//! Reads the symbols from stdin, and writes the table to the file given as
+//! the only argument.
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead};
+use std::process;
+use utils::symbols::{self, EncodeError, Symbol};
+
+/// Parse a line of nm output, keeping only functions. Lines are either
+/// `address size type name` or, if the size is unknown, `address type name`.
+fn parse_line(line: &str) -> Option<Symbol<'_>> {
+    let mut fields = line.splitn(4, ' ');
+    let address = u64::from_str_radix(fields.next()?, 16).ok()?;
+    let second = fields.next()?;
+    let (size, kind, name) = if second.len() == 1 {
+        let rest = line.splitn(3, ' ').nth(2)?;
+        (0, second, rest)
+    } else {
+        (u32::from_str_radix(second, 16).ok()?, fields.next()?, fields.next()?)
+    };
+    match kind {
+        "T" | "t" | "W" | "w" => Some(Symbol { name: strip_hash(name), address, size }),
+        _ => None,
+    }
+}
+
+/// Remove the `::h0123456789abcdef` hash that Rust adds to demangled names
+fn strip_hash(name: &str) -> &str {
+    match name.rfind("::h") {
+        Some(i) if name.len() - i == 19 && name[i + 3..].bytes().all(|b| b.is_ascii_hexdigit()) => &name[..i],
+        _ => name,
+    }
+}
+
+/// Make the encoded table of the functions in the nm output
+fn make_table(lines: &[String]) -> Result<Vec<u8>, EncodeError> {
+    let mut symbols: Vec<Symbol> = lines.iter().filter_map(|line| parse_line(line)).collect();
+    // Keep one name for each address, preferring ones with a size
+    symbols.sort_by_key(|s| (s.address, s.size == 0));
+    symbols.dedup_by_key(|s| s.address);
+
+    let mut table = vec![0; symbols::encoded_len(&symbols)];
+    symbols::encode(&symbols, &mut table)?;
+    Ok(table)
+}
+
+fn main() {
+    let output = match env::args().nth(1) {
+        Some(output) => output,
+        None => {
+            eprintln!("Usage: nm --defined-only --print-size --demangle kernel8 | symtab <output>");
+            process::exit(2);
+        },
+    };
+
+    let lines: Vec<String> = io::stdin().lock().lines().collect::<Result<_, _>>().unwrap_or_else(|e| {
+        eprintln!("Failed to read the symbols: {}", e);
+        process::exit(1);
+    });
+    let table = make_table(&lines).unwrap_or_else(|e| {
+        eprintln!("Failed to encode the symbols: {:?}", e);
+        process::exit(1);
+    });
+    if let Err(e) = fs::write(&output, &table) {
+        eprintln!("Failed to write {}: {}", output, e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use utils::symbols::SymbolTable;
+
+    #[test]
+    pub fn parses_nm_lines() {
+        assert_eq!(
+            parse_line("0000000000080000 0000000000000040 T _boot_cores"),
+            Some(Symbol { name: "_boot_cores", address: 0x8_0000, size: 0x40 }),
+        );
+        // Names can contain spaces, and have their hashes removed
+        assert_eq!(
+            parse_line("0000000000081234 000000000000001c t <T as core::any::Any>::type_id::h0123456789abcdef"),
+            Some(Symbol { name: "<T as core::any::Any>::type_id", address: 0x8_1234, size: 0x1c }),
+        );
+        // Symbols without a size
+        assert_eq!(
+            parse_line("0000000000080100 t el1_main"),
+            Some(Symbol { name: "el1_main", address: 0x8_0100, size: 0 }),
+        );
+        // Only functions are kept
+        assert_eq!(parse_line("0000000000090000 0000000000000008 D kernel::log::FILTERS"), None);
+        assert_eq!(parse_line("0000000000090000 B __bss_start"), None);
+        assert_eq!(parse_line("not nm output"), None);
+    }
+
+    #[test]
+    pub fn strips_only_hashes() {
+        assert_eq!(strip_hash("kernel::shell::run::h0123456789abcdef"), "kernel::shell::run");
+        assert_eq!(strip_hash("kernel::shell::run"), "kernel::shell::run");
+        // Too short, or not hex, so it's part of the name
+        assert_eq!(strip_hash("kernel::shell::h0123"), "kernel::shell::h0123");
+        assert_eq!(strip_hash("kernel::shell::hello_there_general"), "kernel::shell::hello_there_general");
+    }
+
+    #[test]
+    pub fn makes_a_sorted_table() {
+        let lines: Vec<String> = [
+            "0000000000080200 0000000000000020 T reset",
+            "0000000000080000 t _boot_cores",
+            "0000000000080000 0000000000000040 T entry",
+            "0000000000090000 0000000000000008 D DATA",
+            "0000000000080100 t el1_main",
+        ].iter().map(|line| line.to_string()).collect();
+
+        let table = make_table(&lines).unwrap();
+        let table = SymbolTable::parse(&table).unwrap();
+        assert_eq!(table.len(), 3);
+        // The name with a size is kept when two are at the same address
+        assert_eq!(table.get(0), Some(Symbol { name: "entry", address: 0x8_0000, size: 0x40 }));
+        assert_eq!(table.get(1), Some(Symbol { name: "el1_main", address: 0x8_0100, size: 0 }));
+        assert_eq!(table.get(2), Some(Symbol { name: "reset", address: 0x8_0200, size: 0x20 }));
+        assert_eq!(table.lookup(0x8_0108), Some((table.get(1).unwrap(), 8)));
+    }
+
+    #[test]
+    pub fn makes_an_empty_table() {
+        let table = make_table(&[]).unwrap();
+        assert_eq!(SymbolTable::parse(&table).map(|table| table.len()), Some(0));
+    }
+}
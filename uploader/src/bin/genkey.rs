@@ -0,0 +1,46 @@
+//! Makes a key for signing kernel images.
+//!
+//! Usage: genkey <secret key>
+//!
+//! The secret key is written to the given file, which is only readable by its
+//! owner, and the public key is written next to it with `.pub` appended. The
+//! kernel has to be built with the public key (in `KERNEL_PUBLIC_KEY`), and
+//! the uploader given the secret key, for updates to be accepted.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::process;
+use utils::signed_image::{self, KEY_LEN};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 1 {
+        eprintln!("Usage: genkey <secret key>");
+        process::exit(2);
+    }
+    if let Err(e) = generate(&args[0]) {
+        eprintln!("genkey: {}", e);
+        process::exit(1);
+    }
+}
+
+fn generate(path: &str) -> io::Result<()> {
+    let mut seed = [0; KEY_LEN];
+    File::open("/dev/urandom")?.read_exact(&mut seed)?;
+
+    // Refuse to overwrite an existing key, because anything signed with it
+    // would stop being accepted
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(&seed)?;
+    let public_path = format!("{}.pub", path);
+    fs::write(&public_path, signed_image::public_key(&seed))?;
+
+    eprintln!("Wrote the secret key to {} and the public key to {}", path, public_path);
+    Ok(())
+}
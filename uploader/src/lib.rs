@@ -0,0 +1,214 @@
+//! Sends a new kernel image to a running board, using the protocol in the
+//! kernel's `self_update` module.
+//!
+//! The host sends `^` at an empty prompt, followed by the size and CRC-32 of
+//! the image as little endian u32s. The kernel replies with 0x12 once it's
+//! ready to receive the image, or 0x18 if it can't accept it. The image is
+//! then sent in blocks, as described in `utils::block_transfer`, which are
+//! sent again if they're corrupted on the way. Once the whole image has
+//! arrived, the kernel checks it and acknowledges it before copying it over
+//! itself and jumping to the start. If it wasn't intact, or the kernel won't
+//! run it (which it replies to with 0x1A), the old kernel carries on.
+//!
+//! The kernel is sent with a header that lets the kernel check where it came
+//! from, as described in `utils::signed_image`.
+
+pub mod serial;
+pub mod terminal;
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use utils::block_transfer::{self, ACK, BLOCK_SIZE, CANCEL, END, MAX_RETRIES, NAK};
+use utils::crc;
+use utils::signed_image::{self, KEY_LEN};
+
+/// Sent at the start, to discard anything that's been typed at the prompt
+const CTRL_C: u8 = 0x03;
+/// Asks the kernel to update itself, when it's the first character of a line
+const START: u8 = b'^';
+/// Sent by the kernel when it's ready to receive the image in blocks
+const READY: u8 = 0x12;
+/// Sent by the kernel when it won't accept the image. This is the same as
+/// `CANCEL`, which the kernel sends if it gives up part way through.
+const REJECTED: u8 = 0x18;
+/// Sent by the kernel instead of `ACK` when the image isn't signed with the
+/// right key
+const UNTRUSTED: u8 = 0x1A;
+
+/// How long to wait for the kernel
+#[derive(Clone, Copy, Debug)]
+pub struct Timeouts {
+    /// How long to wait for each reply before trying again. This has to be
+    /// longer than the kernel waits before giving up on a block.
+    pub reply: Duration,
+    /// How long the whole upload can take
+    pub transfer: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            reply: Duration::from_secs(3),
+            transfer: Duration::from_secs(300),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    Io(io::Error),
+    /// The image is too large for its size to be sent
+    TooLarge(usize),
+    /// The kernel doesn't have room for the image
+    Rejected,
+    /// The kernel didn't receive the image intact
+    Corrupted,
+    /// The kernel won't run the image, because it isn't signed with the
+    /// right key
+    Untrusted,
+    /// The kernel gave up on the upload
+    Cancelled,
+    /// Something was sent `MAX_RETRIES` times without being accepted
+    TooManyRetries,
+    /// The kernel didn't reply, or the upload took too long
+    Timeout,
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UploadError::Io(e) => write!(f, "{}", e),
+            UploadError::TooLarge(size) => write!(f, "the image is too large ({} bytes)", size),
+            UploadError::Rejected => write!(f, "the kernel rejected the image"),
+            UploadError::Corrupted => write!(f, "the image was corrupted on the way to the kernel"),
+            UploadError::Untrusted => write!(f, "the kernel won't run the image, because it isn't signed with the right key"),
+            UploadError::Cancelled => write!(f, "the kernel cancelled the upload"),
+            UploadError::TooManyRetries => write!(f, "the kernel kept refusing the same block"),
+            UploadError::Timeout => write!(f, "the kernel didn't reply"),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+impl From<io::Error> for UploadError {
+    fn from(e: io::Error) -> UploadError {
+        UploadError::Io(e)
+    }
+}
+
+pub type Result<T> = ::std::result::Result<T, UploadError>;
+
+/// Add the header to `kernel`, signing it with `key` if there is one
+pub fn make_image(kernel: &[u8], key: Option<&[u8; KEY_LEN]>) -> Vec<u8> {
+    let mut image = signed_image::header(kernel, key).to_vec();
+    image.extend_from_slice(kernel);
+    image
+}
+
+/// Read a key (made by `genkey`) from a file
+pub fn read_key<P: AsRef<Path>>(path: P) -> io::Result<[u8; KEY_LEN]> {
+    let key = fs::read(path)?;
+    if key.len() != KEY_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("keys should be {} bytes", KEY_LEN)));
+    }
+    let mut bytes = [0; KEY_LEN];
+    bytes.copy_from_slice(&key);
+    Ok(bytes)
+}
+
+/// Send `image`, made by `make_image`, to the kernel on the other end of
+/// `port`, calling `progress` with the number of bytes that have been accepted
+/// so far and the total.
+///
+/// Reads from `port` should time out by returning 0 bytes, like a `Serial`
+/// does, so that a kernel that isn't listening can be detected. Anything the
+/// kernel prints before it replies is discarded.
+pub fn upload<P, F>(port: &mut P, image: &[u8], timeouts: &Timeouts, mut progress: F) -> Result<()>
+where
+    P: Read + Write,
+    F: FnMut(usize, usize),
+{
+    if image.len() > u32::MAX as usize {
+        return Err(UploadError::TooLarge(image.len()));
+    }
+    port.write_all(&[CTRL_C, START])?;
+    port.write_all(&(image.len() as u32).to_le_bytes())?;
+    port.write_all(&crc::crc32(image).to_le_bytes())?;
+    port.flush()?;
+    match wait_for_reply(port, timeouts.reply)? {
+        Some(READY) => (),
+        Some(_) => return Err(UploadError::Rejected),
+        None => return Err(UploadError::Timeout),
+    }
+
+    let deadline = Instant::now() + timeouts.transfer;
+    progress(0, image.len());
+    for (i, data) in image.chunks(BLOCK_SIZE).enumerate() {
+        let block = block_transfer::encode_block((i + 1) as u8, data);
+        send_block(port, &block, timeouts.reply, deadline)?;
+        progress(usize::min((i + 1) * BLOCK_SIZE, image.len()), image.len());
+    }
+
+    // Once it has every block, the kernel checks the whole image. If that
+    // fails, sending it again won't help.
+    for _ in 0..MAX_RETRIES {
+        match exchange(port, &[END], timeouts.reply, deadline) {
+            Ok(Some(ACK)) => return Ok(()),
+            Ok(Some(NAK)) => return Err(UploadError::Corrupted),
+            Ok(Some(UNTRUSTED)) => return Err(UploadError::Untrusted),
+            Ok(_) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    port.write_all(&[CANCEL])?;
+    Err(UploadError::TooManyRetries)
+}
+
+/// Send a block until the kernel accepts it
+fn send_block<P: Read + Write>(port: &mut P, block: &[u8], reply_timeout: Duration, deadline: Instant) -> Result<()> {
+    for _ in 0..MAX_RETRIES {
+        // If the kernel didn't get it, or the reply was lost, send it again
+        if exchange(port, block, reply_timeout, deadline)? == Some(ACK) {
+            return Ok(());
+        }
+    }
+    port.write_all(&[CANCEL])?;
+    Err(UploadError::TooManyRetries)
+}
+
+/// Send `data`, and return the kernel's reply, or `None` if it didn't reply
+fn exchange<P: Read + Write>(port: &mut P, data: &[u8], reply_timeout: Duration, deadline: Instant) -> Result<Option<u8>> {
+    if Instant::now() >= deadline {
+        port.write_all(&[CANCEL])?;
+        return Err(UploadError::Timeout);
+    }
+    port.write_all(data)?;
+    port.flush()?;
+    match wait_for_reply(port, reply_timeout)? {
+        Some(CANCEL) => Err(UploadError::Cancelled),
+        reply => Ok(reply),
+    }
+}
+
+/// Wait for the kernel to reply, and return the reply, or `None` if it
+/// didn't reply in time
+fn wait_for_reply<P: Read>(port: &mut P, timeout: Duration) -> Result<Option<u8>> {
+    let deadline = Instant::now() + timeout;
+    let mut byte = [0u8];
+    while Instant::now() < deadline {
+        match port.read(&mut byte) {
+            Ok(0) => continue,
+            Ok(_) => match byte[0] {
+                READY | REJECTED | UNTRUSTED | ACK | NAK => return Ok(Some(byte[0])),
+                _ => continue,
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,87 @@
+//! Uploads a kernel to a board over serial, then connects the terminal to it.
+//!
+//! Usage: uploader <device> [--baud <rate>] [--key <secret key>] [image]
+//!
+//! Unless it's given a key to sign the image with, the image will only be
+//! accepted by kernels that are built with the `unsigned-updates` feature.
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+use uploader::serial::Serial;
+use uploader::Timeouts;
+use uploader::terminal::{self, RawMode};
+
+const DEFAULT_BAUD: u32 = 115200;
+const DEFAULT_IMAGE: &str = "kernel8.img";
+
+struct Args {
+    device: String,
+    baud: u32,
+    image: String,
+    key: Option<String>,
+}
+
+fn parse_args() -> Option<Args> {
+    let mut device = None;
+    let mut image = None;
+    let mut baud = DEFAULT_BAUD;
+    let mut key = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--baud" {
+            baud = args.next()?.parse().ok()?;
+        } else if arg == "--key" {
+            key = Some(args.next()?);
+        } else if device.is_none() {
+            device = Some(arg);
+        } else if image.is_none() {
+            image = Some(arg);
+        } else {
+            return None;
+        }
+    }
+    Some(Args {
+        device: device?,
+        baud,
+        image: image.unwrap_or_else(|| DEFAULT_IMAGE.to_string()),
+        key,
+    })
+}
+
+fn main() {
+    let args = match parse_args() {
+        Some(args) => args,
+        None => {
+            eprintln!("Usage: uploader <device> [--baud <rate>] [--key <secret key>] [image]");
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(&args) {
+        eprintln!("uploader: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let kernel = fs::read(&args.image).map_err(|e| format!("{}: {}", args.image, e))?;
+    let key = match &args.key {
+        Some(path) => Some(uploader::read_key(path).map_err(|e| format!("{}: {}", path, e))?),
+        None => None,
+    };
+    let image = uploader::make_image(&kernel, key.as_ref());
+    let mut port = Serial::open(&args.device, args.baud).map_err(|e| format!("{}: {}", args.device, e))?;
+
+    eprintln!("Sending {} ({} bytes) to {}", args.image, image.len(), args.device);
+    uploader::upload(&mut port, &image, &Timeouts::default(), |sent, total| {
+        eprint!("\r{:3}% {}/{} bytes", sent * 100 / total.max(1), sent, total);
+        let _ = io::stderr().flush();
+    })?;
+    eprintln!("\nDone. Press Ctrl-] to exit.");
+
+    let stdin = io::stdin();
+    let _raw = RawMode::enable(&stdin)?;
+    terminal::passthrough(&port, stdin.lock(), io::stdout())?;
+    Ok(())
+}
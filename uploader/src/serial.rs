@@ -0,0 +1,114 @@
+//! Serial ports (or pseudo-terminals) in raw mode
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+
+/// How long a read waits for data before returning nothing, in tenths of a
+/// second
+const READ_TIMEOUT: libc::cc_t = 1;
+
+/// A serial port, configured for 8N1 with no flow control or line processing.
+/// Reads return 0 bytes if nothing arrives for a short while, rather than
+/// blocking forever.
+pub struct Serial {
+    file: File,
+}
+
+impl Serial {
+    pub fn open<P: AsRef<Path>>(path: P, baud: u32) -> io::Result<Serial> {
+        let speed = speed(baud).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("unsupported baud rate {}", baud))
+        })?;
+        // O_NOCTTY stops the port becoming our controlling terminal
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(path)?;
+
+        let mut termios = get_attrs(file.as_raw_fd())?;
+        unsafe {
+            libc::cfmakeraw(&mut termios);
+            check(libc::cfsetspeed(&mut termios, speed))?;
+        }
+        termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+        termios.c_cflag &= !(libc::CSTOPB | libc::CRTSCTS);
+        termios.c_cc[libc::VMIN] = 0;
+        termios.c_cc[libc::VTIME] = READ_TIMEOUT;
+        set_attrs(file.as_raw_fd(), &termios)?;
+
+        Ok(Serial { file })
+    }
+
+    /// Get another handle to the same port, so it can be read and written
+    /// from different threads
+    pub fn try_clone(&self) -> io::Result<Serial> {
+        Ok(Serial {
+            file: self.file.try_clone()?,
+        })
+    }
+
+    /// Wait until everything that has been written has been sent
+    pub fn drain(&self) -> io::Result<()> {
+        check(unsafe { libc::tcdrain(self.file.as_raw_fd()) })
+    }
+}
+
+impl Read for Serial {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for Serial {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl AsRawFd for Serial {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+fn speed(baud: u32) -> Option<libc::speed_t> {
+    Some(match baud {
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        230400 => libc::B230400,
+        460800 => libc::B460800,
+        921600 => libc::B921600,
+        1000000 => libc::B1000000,
+        2000000 => libc::B2000000,
+        _ => return None,
+    })
+}
+
+pub(crate) fn get_attrs(fd: RawFd) -> io::Result<libc::termios> {
+    let mut termios = unsafe { std::mem::zeroed() };
+    check(unsafe { libc::tcgetattr(fd, &mut termios) })?;
+    Ok(termios)
+}
+
+pub(crate) fn set_attrs(fd: RawFd, termios: &libc::termios) -> io::Result<()> {
+    check(unsafe { libc::tcsetattr(fd, libc::TCSANOW, termios) })
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
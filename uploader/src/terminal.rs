@@ -0,0 +1,86 @@
+//! Connects the local terminal to the serial port, so the kernel's shell can
+//! be used once it has been uploaded
+
+use crate::serial::{self, Serial};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+/// Typed to leave the passthrough, like telnet
+pub const ESCAPE: u8 = 0x1D;
+
+/// Puts a terminal into raw mode, so that each key is sent as it's pressed
+/// without being echoed, and restores it when dropped
+pub struct RawMode {
+    fd: RawFd,
+    original: libc::termios,
+}
+
+impl RawMode {
+    /// Put `fd` into raw mode, or return `None` if it isn't a terminal
+    pub fn enable<F: AsRawFd>(file: &F) -> io::Result<Option<RawMode>> {
+        let fd = file.as_raw_fd();
+        if unsafe { libc::isatty(fd) } == 0 {
+            return Ok(None);
+        }
+        let original = serial::get_attrs(fd)?;
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        serial::set_attrs(fd, &raw)?;
+        Ok(Some(RawMode { fd, original }))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = serial::set_attrs(self.fd, &self.original);
+    }
+}
+
+/// Copy everything from `input` to the port, and from the port to `output`,
+/// until `input` ends or `ESCAPE` is read from it
+pub fn passthrough<I, O>(port: &Serial, mut input: I, mut output: O) -> io::Result<()>
+where
+    I: Read,
+    O: Write + Send,
+{
+    let mut reader = port.try_clone()?;
+    let mut writer = port.try_clone()?;
+    let finished = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        let received = scope.spawn(|| -> io::Result<()> {
+            let mut buf = [0u8; 256];
+            // Reads from the port time out, so this notices when it's time
+            // to stop
+            while !finished.load(Ordering::Relaxed) {
+                let len = reader.read(&mut buf)?;
+                if len > 0 {
+                    output.write_all(&buf[..len])?;
+                    output.flush()?;
+                }
+            }
+            Ok(())
+        });
+
+        let sent = (|| -> io::Result<()> {
+            let mut buf = [0u8; 256];
+            loop {
+                let len = input.read(&mut buf)?;
+                if len == 0 {
+                    return Ok(());
+                }
+                let end = buf[..len].iter().position(|&b| b == ESCAPE);
+                writer.write_all(&buf[..end.unwrap_or(len)])?;
+                if end.is_some() {
+                    return Ok(());
+                }
+            }
+        })();
+        finished.store(true, Ordering::Relaxed);
+
+        let received = received.join().expect("the receiving thread panicked");
+        sent.and(received)
+    })
+}
@@ -0,0 +1,295 @@
+//! Runs the uploader against a pseudo-terminal, with a thread on the other end
+//! playing the part of the kernel
+
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+use uploader::serial::Serial;
+use uploader::{terminal, Timeouts, UploadError};
+use utils::block_transfer::{self, Receiver, Step};
+use utils::crc;
+use utils::signed_image::{self, Policy, KEY_LEN};
+
+/// A pseudo-terminal. The uploader opens `path`, and the test uses `master`.
+struct Pty {
+    master: File,
+    path: String,
+    _slave: File,
+}
+
+fn open_pty() -> Pty {
+    let mut master = 0;
+    let mut slave = 0;
+    let mut name = [0 as libc::c_char; 64];
+    let result = unsafe {
+        libc::openpty(&mut master, &mut slave, name.as_mut_ptr(), std::ptr::null(), std::ptr::null())
+    };
+    assert_eq!(result, 0, "openpty failed: {}", io::Error::last_os_error());
+    unsafe {
+        Pty {
+            master: File::from_raw_fd(master),
+            path: CStr::from_ptr(name.as_ptr()).to_str().unwrap().to_string(),
+            _slave: File::from_raw_fd(slave),
+        }
+    }
+}
+
+/// Read the start of the handshake, returning the size and CRC that were sent
+fn read_request(kernel: &mut File) -> (usize, u32) {
+    let mut request = [0u8; 10];
+    kernel.read_exact(&mut request).unwrap();
+    assert_eq!(&request[..2], b"\x03^");
+    let size = u32::from_le_bytes([request[2], request[3], request[4], request[5]]);
+    let crc = u32::from_le_bytes([request[6], request[7], request[8], request[9]]);
+    (size as usize, crc)
+}
+
+/// The key that the test images are signed with
+const SECRET_KEY: [u8; KEY_LEN] = [42; KEY_LEN];
+
+/// Play the part of the kernel, receiving an image in blocks, and returning
+/// the kernel in it. If `corrupt` is given, that byte of the transfer is
+/// corrupted on the way.
+///
+/// The master has to be kept open until the uploader has finished, or it
+/// can't read the last reply.
+fn receive_image(kernel: &mut File, corrupt: Option<usize>) -> Option<Vec<u8>> {
+    let (size, crc) = read_request(kernel);
+    // The shell might print something before the reply
+    kernel.write_all(b"\r\n> ").unwrap();
+    kernel.write_all(&[0x12]).unwrap();
+
+    let mut image = vec![0; size];
+    let mut receiver = Receiver::new(&mut image);
+    let mut count = 0;
+    loop {
+        let mut message = vec![0];
+        kernel.read_exact(&mut message).unwrap();
+        if message[0] == block_transfer::START {
+            message.resize(block_transfer::BLOCK_LEN, 0);
+            kernel.read_exact(&mut message[1..]).unwrap();
+        }
+        let mut step = Step::Continue;
+        for mut byte in message {
+            if corrupt == Some(count) {
+                byte ^= 1;
+            }
+            count += 1;
+            step = receiver.feed(byte);
+        }
+        // A real kernel would wait for the rest of a corrupted block
+        if step == Step::Continue {
+            step = receiver.timeout();
+        }
+        match step {
+            Step::Reply(reply) => kernel.write_all(&[reply]).unwrap(),
+            Step::Finished => break,
+            step => panic!("unexpected {:?}", step),
+        }
+    }
+
+    if crc::crc32(&image) != crc {
+        kernel.write_all(&[block_transfer::NAK]).unwrap();
+        return None;
+    }
+    let public_key = signed_image::public_key(&SECRET_KEY);
+    match signed_image::verify(&image, Some(&public_key), Policy::SignedOnly) {
+        Ok(verified) => {
+            kernel.write_all(&[block_transfer::ACK]).unwrap();
+            Some(verified.kernel.to_vec())
+        },
+        Err(_) => {
+            // UNTRUSTED
+            kernel.write_all(&[0x1A]).unwrap();
+            None
+        },
+    }
+}
+
+fn test_kernel() -> Vec<u8> {
+    (0..10_000).map(|i| (i * 7 % 256) as u8).collect()
+}
+
+#[test]
+pub fn uploads_the_image() {
+    let pty = open_pty();
+    let image = uploader::make_image(&test_kernel(), Some(&SECRET_KEY));
+    let mut kernel = pty.master;
+    let received = thread::spawn(move || (receive_image(&mut kernel, None), kernel));
+
+    let mut port = Serial::open(&pty.path, 115200).unwrap();
+    let mut reports = Vec::new();
+    uploader::upload(&mut port, &image, &Timeouts::default(), |sent, total| reports.push((sent, total))).unwrap();
+
+    assert_eq!(received.join().unwrap().0, Some(test_kernel()));
+    assert_eq!(reports.first(), Some(&(0, image.len())));
+    assert_eq!(reports.last(), Some(&(image.len(), image.len())));
+}
+
+#[test]
+pub fn sends_corrupted_blocks_again() {
+    let pty = open_pty();
+    let image = uploader::make_image(&test_kernel(), Some(&SECRET_KEY));
+    let mut kernel = pty.master;
+    let received = thread::spawn(move || (receive_image(&mut kernel, Some(2 * block_transfer::BLOCK_LEN + 100)), kernel));
+
+    let mut port = Serial::open(&pty.path, 115200).unwrap();
+    uploader::upload(&mut port, &image, &Timeouts::default(), |_, _| ()).unwrap();
+    assert_eq!(received.join().unwrap().0, Some(test_kernel()));
+}
+
+#[test]
+pub fn reports_untrusted_images() {
+    // Neither an unsigned image nor one signed with the wrong key is accepted
+    for key in [None, Some([1; KEY_LEN])].iter() {
+        let pty = open_pty();
+        let mut kernel = pty.master;
+        let received = thread::spawn(move || (receive_image(&mut kernel, None), kernel));
+
+        let mut port = Serial::open(&pty.path, 115200).unwrap();
+        let image = uploader::make_image(&test_kernel(), key.as_ref());
+        let result = uploader::upload(&mut port, &image, &Timeouts::default(), |_, _| ());
+        assert!(matches!(result, Err(UploadError::Untrusted)), "{:?}", result);
+        assert_eq!(received.join().unwrap().0, None);
+    }
+}
+
+#[test]
+pub fn reports_rejection() {
+    let pty = open_pty();
+    let mut kernel = pty.master;
+    let handle = thread::spawn(move || {
+        read_request(&mut kernel);
+        kernel.write_all(&[0x18]).unwrap();
+        kernel
+    });
+
+    let mut port = Serial::open(&pty.path, 115200).unwrap();
+    let result = uploader::upload(&mut port, &[1, 2, 3], &Timeouts::default(), |_, _| ());
+    assert!(matches!(result, Err(UploadError::Rejected)), "{:?}", result);
+    handle.join().unwrap();
+}
+
+#[test]
+pub fn reports_corruption() {
+    let pty = open_pty();
+    let mut kernel = pty.master;
+    let handle = thread::spawn(move || {
+        read_request(&mut kernel);
+        kernel.write_all(&[0x12]).unwrap();
+        // Accept the block, but then find that the image doesn't match
+        let mut block = [0; block_transfer::BLOCK_LEN];
+        kernel.read_exact(&mut block).unwrap();
+        kernel.write_all(&[block_transfer::ACK]).unwrap();
+        let mut end = [0];
+        kernel.read_exact(&mut end).unwrap();
+        assert_eq!(end, [block_transfer::END]);
+        kernel.write_all(&[block_transfer::NAK]).unwrap();
+        kernel
+    });
+
+    let mut port = Serial::open(&pty.path, 115200).unwrap();
+    let result = uploader::upload(&mut port, &[1, 2, 3], &Timeouts::default(), |_, _| ());
+    assert!(matches!(result, Err(UploadError::Corrupted)), "{:?}", result);
+    handle.join().unwrap();
+}
+
+#[test]
+pub fn reports_cancellation() {
+    let pty = open_pty();
+    let mut kernel = pty.master;
+    let handle = thread::spawn(move || {
+        read_request(&mut kernel);
+        kernel.write_all(&[0x12]).unwrap();
+        let mut block = [0; block_transfer::BLOCK_LEN];
+        kernel.read_exact(&mut block).unwrap();
+        kernel.write_all(&[block_transfer::ACK]).unwrap();
+        // Give up instead of checking the image, as if the transfer had
+        // timed out
+        let mut end = [0];
+        kernel.read_exact(&mut end).unwrap();
+        kernel.write_all(&[block_transfer::CANCEL]).unwrap();
+        kernel
+    });
+
+    let mut port = Serial::open(&pty.path, 115200).unwrap();
+    let result = uploader::upload(&mut port, &[1, 2, 3], &Timeouts::default(), |_, _| ());
+    assert!(matches!(result, Err(UploadError::Cancelled)), "{:?}", result);
+    handle.join().unwrap();
+}
+
+#[test]
+pub fn times_out_without_a_reply() {
+    let pty = open_pty();
+    let mut port = Serial::open(&pty.path, 115200).unwrap();
+    let timeouts = Timeouts {
+        reply: Duration::from_millis(300),
+        transfer: Duration::from_secs(1),
+    };
+    let result = uploader::upload(&mut port, &[1, 2, 3], &timeouts, |_, _| ());
+    assert!(matches!(result, Err(UploadError::Timeout)), "{:?}", result);
+}
+
+/// Input that arrives when the test sends it
+struct ChannelReader(mpsc::Receiver<Vec<u8>>);
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.recv() {
+            Ok(data) => {
+                buf[..data.len()].copy_from_slice(&data);
+                Ok(data.len())
+            },
+            Err(_) => Ok(0),
+        }
+    }
+}
+
+/// Output that the test can wait for
+struct ChannelWriter(Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let _ = self.0.send(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+pub fn passes_the_terminal_through() {
+    let pty = open_pty();
+    let mut kernel = pty.master;
+    let shell = thread::spawn(move || {
+        let mut line = [0u8; 3];
+        kernel.read_exact(&mut line).unwrap();
+        kernel.write_all(b"hello").unwrap();
+        // Keep the master open, otherwise the output is lost when it hangs up
+        (line, kernel)
+    });
+
+    let port = Serial::open(&pty.path, 115200).unwrap();
+    let (input, keys) = mpsc::channel();
+    let (screen, output) = mpsc::channel();
+    let terminal = thread::spawn(move || terminal::passthrough(&port, ChannelReader(keys), ChannelWriter(screen)));
+
+    input.send(b"ls\r".to_vec()).unwrap();
+    let mut shown = Vec::new();
+    while shown.len() < 5 {
+        shown.extend(output.recv_timeout(Duration::from_secs(5)).unwrap());
+    }
+    assert_eq!(shown, b"hello");
+    let (line, _kernel) = shell.join().unwrap();
+    assert_eq!(&line, b"ls\r");
+
+    // Anything after the escape isn't sent
+    input.send(vec![terminal::ESCAPE, b'x']).unwrap();
+    terminal.join().unwrap().unwrap();
+}
@@ -0,0 +1,141 @@
+//! Walking the chain of frame records to find the return addresses on the
+//! stack.
+//!
+//! With frame pointers enabled, each function's prologue pushes a frame record
+//! holding the caller's frame pointer (x29) and the return address (x30), and
+//! points x29 at it. Following the saved frame pointers gives a backtrace
+//! without needing any unwind tables.
+
+use core::ptr;
+
+/// The most frames that will be followed, in case the chain has a loop that
+/// the other checks don't catch
+pub const MAX_FRAMES: usize = 64;
+
+/// The range of addresses that the stack occupies, so that a corrupt frame
+/// pointer can't send the walk off into arbitrary memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackBounds {
+    /// The lowest address in the stack
+    pub low: usize,
+    /// The address just past the top of the stack
+    pub high: usize,
+}
+
+impl StackBounds {
+    /// Whether a whole frame record at `address` lies within the stack
+    fn contains_record(&self, address: usize) -> bool {
+        address >= self.low && matches!(address.checked_add(16), Some(end) if end <= self.high)
+    }
+}
+
+/// An iterator over the return addresses in a chain of frame records, from
+/// the innermost outwards
+pub struct Frames {
+    frame_pointer: usize,
+    bounds: StackBounds,
+    count: usize,
+}
+
+impl Frames {
+    /// Walk the chain starting at the frame record that `frame_pointer`
+    /// points to.
+    ///
+    /// # Safety
+    ///
+    /// All of the memory within `bounds` must be readable.
+    pub unsafe fn new(frame_pointer: usize, bounds: StackBounds) -> Frames {
+        Frames {
+            frame_pointer,
+            bounds,
+            count: 0,
+        }
+    }
+}
+
+impl Iterator for Frames {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let fp = self.frame_pointer;
+        if self.count == MAX_FRAMES || fp & 7 != 0 || !self.bounds.contains_record(fp) {
+            return None;
+        }
+        // Safe because the record is within the bounds, which the caller of
+        // `new` promised are readable
+        let (next, return_address) = unsafe {
+            (ptr::read_volatile(fp as *const usize), ptr::read_volatile((fp + 8) as *const usize))
+        };
+        if return_address == 0 {
+            return None;
+        }
+
+        // Callers' frames are always further up the stack, so anything else
+        // means the chain is corrupt and the walk stops after this frame
+        self.frame_pointer = if next > fp { next } else { 0 };
+        self.count += 1;
+        Some(return_address)
+    }
+}
+
+/// Get the current frame pointer, which points at the frame record of the
+/// function that calls this
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+pub fn frame_pointer() -> usize {
+    let fp: usize;
+    unsafe {
+        asm!("MOV     $0, x29" : "=r"(fp) ::: "volatile");
+    }
+    fp
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+pub fn frame_pointer() -> usize {
+    // Frame records can't be relied upon when running on the host
+    0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::vec::Vec;
+
+    /// Build a stack of frame records, each pointing at the next, and return
+    /// the stack with the address of the first record
+    fn stack(return_addresses: &[usize]) -> (Vec<usize>, usize) {
+        let mut stack = std::vec![0usize; return_addresses.len() * 2 + 2];
+        let base = stack.as_ptr() as usize;
+        for (i, &address) in return_addresses.iter().enumerate() {
+            stack[i * 2] = base + (i + 1) * 16;
+            stack[i * 2 + 1] = address;
+        }
+        (stack, base)
+    }
+
+    fn bounds(stack: &[usize]) -> StackBounds {
+        let low = stack.as_ptr() as usize;
+        StackBounds { low, high: low + stack.len() * 8 }
+    }
+
+    #[test]
+    pub fn follows_the_chain() {
+        let (stack, fp) = stack(&[0x1000, 0x2000, 0x3000]);
+        let frames: Vec<usize> = unsafe { Frames::new(fp, bounds(&stack)) }.collect();
+        assert_eq!(frames, [0x1000, 0x2000, 0x3000]);
+    }
+
+    #[test]
+    pub fn stops_at_bad_frame_pointers() {
+        let (mut stack, fp) = stack(&[0x1000, 0x2000, 0x3000]);
+        // A frame pointer that goes back down the stack would loop forever
+        stack[2] = fp;
+        let frames: Vec<usize> = unsafe { Frames::new(fp, bounds(&stack)) }.collect();
+        assert_eq!(frames, [0x1000, 0x2000]);
+
+        // Nothing outside the bounds is read
+        let frames: Vec<usize> = unsafe { Frames::new(fp + stack.len() * 8, bounds(&stack)) }.collect();
+        assert!(frames.is_empty());
+        assert_eq!(unsafe { Frames::new(fp + 4, bounds(&stack)) }.count(), 0);
+    }
+}
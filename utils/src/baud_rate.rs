@@ -0,0 +1,124 @@
+//! Baud rate divisor calculations for the UARTs, which have to make do with
+//! whatever clock rate the firmware gives them.
+
+/// The baud rate that a UART is actually running at, which differs from the
+/// requested one because the divisors can't represent every rate exactly
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BaudRate {
+    pub requested: u32,
+    pub actual: u32,
+}
+
+impl BaudRate {
+    /// The error in the actual baud rate, in parts per million of the
+    /// requested rate. Anything within a couple of percent (±20000) should
+    /// work reliably.
+    pub fn error_ppm(&self) -> i32 {
+        let diff = i64::from(self.actual) - i64::from(self.requested);
+        (diff * 1_000_000 / i64::from(self.requested)) as i32
+    }
+}
+
+/// The requested baud rate can't be produced from the given clock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnachievableBaudRate {
+    pub baud_rate: u32,
+    pub clock_rate: u32,
+}
+
+/// Calculate the integer and fractional baud rate divisors for a PL011 (UART0)
+/// with the given reference clock rate. The PL011 divides the clock by
+/// 16 * (IBRD + FBRD / 64).
+pub fn pl011_divisors(clock_rate: u32, baud_rate: u32) -> Result<(u32, u32, BaudRate), UnachievableBaudRate> {
+    let unachievable = UnachievableBaudRate { baud_rate, clock_rate };
+    if baud_rate == 0 {
+        return Err(unachievable);
+    }
+
+    // divisor * 64 = clock_rate * 64 / (16 * baud_rate), rounded to nearest
+    let divisor_x64 = (u64::from(clock_rate) * 4 + u64::from(baud_rate) / 2) / u64::from(baud_rate);
+    let ibrd = divisor_x64 >> 6;
+    if ibrd == 0 || ibrd > 0xFFFF {
+        return Err(unachievable);
+    }
+
+    let actual = (u64::from(clock_rate) * 4 / divisor_x64) as u32;
+    Ok((ibrd as u32, (divisor_x64 & 0x3F) as u32, BaudRate { requested: baud_rate, actual }))
+}
+
+/// Calculate the baud rate register value for the mini UART, given the core
+/// clock rate. The mini UART divides the core clock by 8 * (BAUD + 1).
+pub fn mini_uart_divisor(clock_rate: u32, baud_rate: u32) -> Result<(u32, BaudRate), UnachievableBaudRate> {
+    let unachievable = UnachievableBaudRate { baud_rate, clock_rate };
+    if baud_rate == 0 {
+        return Err(unachievable);
+    }
+
+    // Round to the nearest divisor
+    let divisor = (u64::from(clock_rate) + u64::from(baud_rate) * 4) / (u64::from(baud_rate) * 8);
+    if divisor == 0 || divisor > 0x1_0000 {
+        return Err(unachievable);
+    }
+
+    let actual = (u64::from(clock_rate) / (divisor * 8)) as u32;
+    Ok(((divisor - 1) as u32, BaudRate { requested: baud_rate, actual }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CLOCK: u32 = 48_000_000;
+
+    #[test]
+    pub fn pl011_standard_rates() {
+        let (ibrd, fbrd, rate) = pl011_divisors(CLOCK, 9600).unwrap();
+        assert_eq!((ibrd, fbrd), (312, 32));
+        assert_eq!(rate, BaudRate { requested: 9600, actual: 9600 });
+        assert_eq!(rate.error_ppm(), 0);
+
+        let (ibrd, fbrd, rate) = pl011_divisors(CLOCK, 115_200).unwrap();
+        assert_eq!((ibrd, fbrd), (26, 3));
+        assert_eq!(rate.actual, 115_176);
+        assert_eq!(rate.error_ppm(), -208);
+
+        let (ibrd, fbrd, rate) = pl011_divisors(CLOCK, 921_600).unwrap();
+        assert_eq!((ibrd, fbrd), (3, 16));
+        assert_eq!(rate.actual, 923_076);
+        assert_eq!(rate.error_ppm(), 1601);
+    }
+
+    #[test]
+    pub fn pl011_rounds_the_fractional_divisor() {
+        // The exact divisor is 6.5104, which is 416.67 / 64, so FBRD rounds up
+        // to 33 rather than being truncated to 32
+        let (ibrd, fbrd, rate) = pl011_divisors(CLOCK, 460_800).unwrap();
+        assert_eq!((ibrd, fbrd), (6, 33));
+        assert_eq!(rate.actual, 460_431);
+        // 115200 is 26.04, which rounds down
+        assert_eq!(pl011_divisors(CLOCK, 115_200).unwrap().1, 3);
+    }
+
+    #[test]
+    pub fn pl011_unachievable_rates() {
+        let error = |baud_rate| Err(UnachievableBaudRate { baud_rate, clock_rate: CLOCK });
+        assert_eq!(pl011_divisors(CLOCK, 0), error(0));
+        // IBRD would be 0
+        assert_eq!(pl011_divisors(CLOCK, 4_000_000), error(4_000_000));
+        // IBRD would be more than 0xFFFF
+        assert_eq!(pl011_divisors(CLOCK, 40), error(40));
+        assert!(pl011_divisors(CLOCK, 46).is_ok());
+    }
+
+    #[test]
+    pub fn mini_uart_rates() {
+        let (divisor, rate) = mini_uart_divisor(250_000_000, 115_200).unwrap();
+        assert_eq!(divisor, 270);
+        assert_eq!(rate, BaudRate { requested: 115_200, actual: 115_313 });
+
+        let error = |baud_rate| Err(UnachievableBaudRate { baud_rate, clock_rate: 250_000_000 });
+        assert_eq!(mini_uart_divisor(250_000_000, 0), error(0));
+        assert_eq!(mini_uart_divisor(250_000_000, 100), error(100));
+        assert_eq!(mini_uart_divisor(250_000_000, 100_000_000), error(100_000_000));
+    }
+}
@@ -0,0 +1,283 @@
+//! A protocol for sending a file reliably over a serial line, similar to
+//! XMODEM-1K.
+//!
+//! The file is split into blocks of `BLOCK_SIZE` bytes, with the last padded
+//! with zeros. Each block is sent as `START`, the block number, the block
+//! number inverted, the data, and the CRC-32 of the block number and data (in
+//! little endian). Block numbers start at 1, and wrap around after 255.
+//!
+//! The receiver replies with `ACK` to accept a block, or `NAK` to have it sent
+//! again. If an `ACK` is lost, the sender will send the same block again, so
+//! the receiver accepts it again without storing it. Once every block has been
+//! accepted, the sender sends `END`, and the receiver replies with `ACK` if the
+//! whole file is intact or `NAK` if it isn't. Either side can give up by
+//! sending `CANCEL`.
+
+use crate::crc::Crc32;
+
+/// The number of bytes of the file in each block
+pub const BLOCK_SIZE: usize = 1024;
+/// The number of bytes that each block takes to send
+pub const BLOCK_LEN: usize = 3 + BLOCK_SIZE + 4;
+
+/// Starts a block
+pub const START: u8 = 0x02;
+/// Sent instead of a block once the whole file has been sent
+pub const END: u8 = 0x04;
+pub const ACK: u8 = 0x06;
+pub const NAK: u8 = 0x15;
+pub const CANCEL: u8 = 0x18;
+
+/// How many times in a row a block can fail before giving up
+pub const MAX_RETRIES: u32 = 10;
+
+/// Encode block number `number`, which holds `data`
+pub fn encode_block(number: u8, data: &[u8]) -> [u8; BLOCK_LEN] {
+    assert!(data.len() <= BLOCK_SIZE, "blocks can only hold {} bytes", BLOCK_SIZE);
+    let mut block = [0; BLOCK_LEN];
+    block[0] = START;
+    block[1] = number;
+    block[2] = !number;
+    block[3..3 + data.len()].copy_from_slice(data);
+
+    let mut crc = Crc32::new();
+    crc.update(&block[1..2]);
+    crc.update(&block[3..3 + BLOCK_SIZE]);
+    block[3 + BLOCK_SIZE..].copy_from_slice(&crc.finish().to_le_bytes());
+    block
+}
+
+/// Why a transfer failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferError {
+    /// The sender gave up
+    Cancelled,
+    /// A block failed `MAX_RETRIES` times in a row
+    TooManyErrors,
+    /// The sender finished before the whole file had been sent
+    EndedEarly,
+}
+
+/// What the receiver needs to do after being given a byte
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Wait for the next byte
+    Continue,
+    /// Send this reply to the sender
+    Reply(u8),
+    /// The whole file has arrived. The caller should check it, and reply with
+    /// `ACK` or `NAK`.
+    Finished,
+    /// The transfer has failed. The caller should send `CANCEL`, unless it
+    /// was the sender who cancelled.
+    Failed(TransferError),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Waiting for the start of a block
+    Idle,
+    /// Part way through a block. `pos` is the number of bytes received
+    /// since `START`.
+    Block { number: u8, pos: usize },
+    /// Something went wrong, so ignore everything until the sender stops
+    /// sending, and then ask for the block again
+    Purging,
+}
+
+/// Receives a file into a buffer, one byte at a time. The file must fill
+/// the whole buffer.
+pub struct Receiver<'a> {
+    buf: &'a mut [u8],
+    /// The number of bytes that have been accepted
+    received: usize,
+    /// The number of the next block
+    next: u8,
+    state: State,
+    crc: Crc32,
+    received_crc: [u8; 4],
+    /// The number of times in a row that the current block has failed
+    errors: u32,
+}
+
+impl<'a> Receiver<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Receiver<'a> {
+        Receiver {
+            buf,
+            received: 0,
+            next: 1,
+            state: State::Idle,
+            crc: Crc32::new(),
+            received_crc: [0; 4],
+            errors: 0,
+        }
+    }
+
+    /// The number of bytes that have been accepted so far
+    pub fn received(&self) -> usize {
+        self.received
+    }
+
+    /// Process a byte from the sender
+    pub fn feed(&mut self, byte: u8) -> Step {
+        match self.state {
+            State::Idle => match byte {
+                START => {
+                    self.state = State::Block { number: 0, pos: 0 };
+                    self.crc = Crc32::new();
+                    Step::Continue
+                },
+                END if self.received == self.buf.len() => Step::Finished,
+                END => Step::Failed(TransferError::EndedEarly),
+                CANCEL => Step::Failed(TransferError::Cancelled),
+                _ => self.purge(),
+            },
+            State::Block { number, pos } => self.feed_block(number, pos, byte),
+            State::Purging => Step::Continue,
+        }
+    }
+
+    fn feed_block(&mut self, number: u8, pos: usize, byte: u8) -> Step {
+        self.state = State::Block { number, pos: pos + 1 };
+        match pos {
+            0 => {
+                self.state = State::Block { number: byte, pos: 1 };
+                self.crc.update(&[byte]);
+            },
+            1 => {
+                let expected = number == self.next && self.received < self.buf.len();
+                let repeated = number == self.next.wrapping_sub(1);
+                if byte != !number || !(expected || repeated) {
+                    return self.purge();
+                }
+            },
+            _ if pos < 2 + BLOCK_SIZE => {
+                self.crc.update(&[byte]);
+                // Only the block that's expected is stored, so a repeated
+                // one can't overwrite it
+                let offset = self.received + pos - 2;
+                if number == self.next && offset < self.buf.len() {
+                    self.buf[offset] = byte;
+                }
+            },
+            _ => {
+                let index = pos - 2 - BLOCK_SIZE;
+                self.received_crc[index] = byte;
+                if index == 3 {
+                    return self.finish_block(number);
+                }
+            },
+        }
+        Step::Continue
+    }
+
+    fn finish_block(&mut self, number: u8) -> Step {
+        if self.crc.finish().to_le_bytes() != self.received_crc {
+            return self.purge();
+        }
+        if number == self.next {
+            self.received = usize::min(self.received + BLOCK_SIZE, self.buf.len());
+            self.next = self.next.wrapping_add(1);
+        }
+        self.state = State::Idle;
+        self.errors = 0;
+        Step::Reply(ACK)
+    }
+
+    fn purge(&mut self) -> Step {
+        self.state = State::Purging;
+        Step::Continue
+    }
+
+    /// Tell the receiver that nothing has arrived for a while, so the sender
+    /// has stopped part way through a block, or is waiting for a reply that
+    /// was lost
+    pub fn timeout(&mut self) -> Step {
+        self.state = State::Idle;
+        self.errors += 1;
+        if self.errors >= MAX_RETRIES {
+            Step::Failed(TransferError::TooManyErrors)
+        } else {
+            Step::Reply(NAK)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::vec::Vec;
+
+    fn feed_all(receiver: &mut Receiver, bytes: &[u8]) -> Vec<Step> {
+        bytes.iter()
+            .map(|&b| receiver.feed(b))
+            .filter(|&step| step != Step::Continue)
+            .collect()
+    }
+
+    #[test]
+    pub fn receives_a_file() {
+        let file: Vec<u8> = (0..2500).map(|i| (i % 253) as u8).collect();
+        let mut buf = std::vec![0; file.len()];
+        let mut receiver = Receiver::new(&mut buf);
+        for (i, data) in file.chunks(BLOCK_SIZE).enumerate() {
+            let block = encode_block(i as u8 + 1, data);
+            assert_eq!(feed_all(&mut receiver, &block), [Step::Reply(ACK)]);
+        }
+        assert_eq!(receiver.received(), file.len());
+        assert_eq!(receiver.feed(END), Step::Finished);
+        assert_eq!(buf, file);
+    }
+
+    #[test]
+    pub fn recovers_from_errors() {
+        let file: Vec<u8> = (0..1500).map(|i| (i % 7) as u8).collect();
+        let first = encode_block(1, &file[..BLOCK_SIZE]);
+        let second = encode_block(2, &file[BLOCK_SIZE..]);
+        let mut buf = std::vec![0; file.len()];
+        let mut receiver = Receiver::new(&mut buf);
+
+        // A corrupted block is ignored, and asked for again once the sender
+        // has finished sending it
+        let mut corrupt = first;
+        corrupt[100] ^= 1;
+        assert_eq!(feed_all(&mut receiver, &corrupt), []);
+        assert_eq!(receiver.timeout(), Step::Reply(NAK));
+
+        // So is one with missing bytes
+        assert_eq!(feed_all(&mut receiver, &first[..500]), []);
+        assert_eq!(receiver.timeout(), Step::Reply(NAK));
+
+        assert_eq!(feed_all(&mut receiver, &first), [Step::Reply(ACK)]);
+        // If the ACK is lost, the block is sent again, and a corrupted copy
+        // of it doesn't overwrite the good one
+        assert_eq!(feed_all(&mut receiver, &corrupt), []);
+        assert_eq!(receiver.timeout(), Step::Reply(NAK));
+        assert_eq!(feed_all(&mut receiver, &first), [Step::Reply(ACK)]);
+        assert_eq!(receiver.received(), BLOCK_SIZE);
+
+        // Blocks from the future are refused
+        assert_eq!(feed_all(&mut receiver, &encode_block(3, &[])), []);
+        assert_eq!(receiver.timeout(), Step::Reply(NAK));
+
+        assert_eq!(feed_all(&mut receiver, &second), [Step::Reply(ACK)]);
+        assert_eq!(receiver.feed(END), Step::Finished);
+        assert_eq!(buf, file);
+    }
+
+    #[test]
+    pub fn gives_up() {
+        let mut buf = [0; 10];
+        let mut receiver = Receiver::new(&mut buf);
+        for _ in 1..MAX_RETRIES {
+            assert_eq!(receiver.timeout(), Step::Reply(NAK));
+        }
+        assert_eq!(receiver.timeout(), Step::Failed(TransferError::TooManyErrors));
+
+        let mut receiver = Receiver::new(&mut buf);
+        assert_eq!(receiver.feed(CANCEL), Step::Failed(TransferError::Cancelled));
+
+        let mut receiver = Receiver::new(&mut buf);
+        assert_eq!(receiver.feed(END), Step::Failed(TransferError::EndedEarly));
+    }
+}
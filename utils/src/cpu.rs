@@ -0,0 +1,88 @@
+//! Information about the core that is currently executing
+
+/// The number of cores on the BCM2837
+pub const MAX_CORES: usize = 4;
+
+/// Get the index of the core that this code is running on.
+///
+/// This reads the value that the boot code (`el1_main` in boot_cores.S) caches
+/// in TPIDR_EL1 on each core before entering Rust.
+#[cfg(target_arch = "aarch64")]
+#[inline]
+pub fn core_id() -> usize {
+    let id: u64;
+    unsafe {
+        asm!("MRS     $0, TPIDR_EL1" : "=r"(id) ::: "volatile");
+    }
+    id as usize
+}
+
+/// Get the index of the core that this code is running on, by decoding the
+/// affinity fields of MPIDR_EL1. This works even before the boot code has set
+/// up TPIDR_EL1.
+#[cfg(target_arch = "aarch64")]
+#[inline]
+pub fn mpidr_core_id() -> usize {
+    let mpidr: u64;
+    unsafe {
+        asm!("MRS     $0, MPIDR_EL1" : "=r"(mpidr) ::: "volatile");
+    }
+    // Aff0 identifies the core within the cluster
+    (mpidr & 0xFF) as usize
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+pub fn mpidr_core_id() -> usize {
+    core_id()
+}
+
+#[cfg(all(not(target_arch = "aarch64"), not(test)))]
+#[inline]
+pub fn core_id() -> usize {
+    0
+}
+
+#[cfg(all(not(target_arch = "aarch64"), test))]
+pub fn core_id() -> usize {
+    TEST_CORE_ID.with(|id| id.get())
+}
+
+/// Make the current thread claim to be running on the given core, for tests of
+/// code that needs real core indices
+#[cfg(all(not(target_arch = "aarch64"), test))]
+pub fn set_test_core_id(id: usize) {
+    TEST_CORE_ID.with(|cell| cell.set(id));
+}
+
+// When testing on the host, treat each thread as if it was a separate core, so
+// that code which relies on core IDs being distinct still works. There can be
+// more threads than cores, so these IDs aren't necessarily below `MAX_CORES`.
+#[cfg(all(not(target_arch = "aarch64"), test))]
+std::thread_local! {
+    static TEST_CORE_ID: core::cell::Cell<usize> = {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        core::cell::Cell::new(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    };
+}
+
+/// Stop this core for good, with interrupts masked so that nothing can wake
+/// it up and run more code
+#[cfg(target_arch = "aarch64")]
+pub fn park() -> ! {
+    unsafe {
+        asm!("MSR     DAIFSET, #0xF" ::: "memory" : "volatile");
+    }
+    loop {
+        unsafe {
+            asm!("WFE" :::: "volatile");
+        }
+    }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+pub fn park() -> ! {
+    loop {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,71 @@
+//! Cyclic redundancy checks, for spotting corrupted data.
+
+/// The CRC-32 of each nibble, for the reflected polynomial 0xEDB88320. This is
+/// a quarter of the speed of a full byte table, but a lot smaller.
+const CRC32_NIBBLES: [u32; 16] = [
+    0x0000_0000, 0x1DB7_1064, 0x3B6E_20C8, 0x26D9_30AC,
+    0x76DC_4190, 0x6B6B_51F4, 0x4DB2_6158, 0x5005_713C,
+    0xEDB8_8320, 0xF00F_9344, 0xD6D6_A3E8, 0xCB61_B38C,
+    0x9B64_C2B0, 0x86D3_D2D4, 0xA00A_E278, 0xBDBD_F21C,
+];
+
+/// Calculates a CRC-32 (as used by zlib and Ethernet) incrementally
+#[derive(Clone, Copy, Debug)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    pub const fn new() -> Crc32 {
+        Crc32 {
+            state: 0xFFFF_FFFF,
+        }
+    }
+
+    /// Add more data to the checksum
+    pub fn update(&mut self, data: &[u8]) {
+        let mut crc = self.state;
+        for &byte in data {
+            crc ^= u32::from(byte);
+            crc = (crc >> 4) ^ CRC32_NIBBLES[(crc & 0xF) as usize];
+            crc = (crc >> 4) ^ CRC32_NIBBLES[(crc & 0xF) as usize];
+        }
+        self.state = crc;
+    }
+
+    /// The checksum of all of the data so far
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Crc32 {
+        Crc32::new()
+    }
+}
+
+/// Calculate the CRC-32 of some data in one go
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn crc32_check_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414F_A339);
+
+        // Feeding the data in pieces gives the same result
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+}
@@ -0,0 +1,260 @@
+//! Framing for sharing one serial line between several streams of data.
+//!
+//! Each frame holds a channel ID, a payload and a CRC-32 of the two, and is
+//! encoded with COBS (Consistent Overhead Byte Stuffing) so that it contains
+//! no zero bytes. A zero byte ends each frame, so the receiver can always find
+//! the start of the next one, whatever was corrupted before it.
+//!
+//! COBS replaces each zero with the distance to the next one. The encoded
+//! frame is a series of blocks, each starting with a code byte `n` followed by
+//! `n - 1` data bytes. A zero follows the block unless `n` is 0xFF, or it's
+//! the last block.
+
+use crate::crc::Crc32;
+
+/// The most data bytes in a block, which is when the code byte is 0xFF
+const MAX_BLOCK: usize = 254;
+/// The channel ID and CRC that are added to the payload
+const OVERHEAD: usize = 1 + 4;
+
+/// Identifies which stream a frame belongs to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Channel(pub u8);
+
+impl Channel {
+    pub const CONSOLE: Channel = Channel(0);
+    pub const LOG: Channel = Channel(1);
+    pub const UPDATE: Channel = Channel(2);
+    pub const COMMAND: Channel = Channel(3);
+    pub const FILE: Channel = Channel(4);
+}
+
+/// The most bytes that a payload of `len` bytes can take once encoded,
+/// including the zero at the end
+pub const fn max_encoded_len(len: usize) -> usize {
+    let unencoded = len + OVERHEAD;
+    unencoded + unencoded / MAX_BLOCK + 2
+}
+
+/// Encode a frame, and pass each byte of it to `write`
+pub fn encode(channel: Channel, payload: &[u8], mut write: impl FnMut(u8)) {
+    let mut crc = Crc32::new();
+    crc.update(&[channel.0]);
+    crc.update(payload);
+    let crc = crc.finish().to_le_bytes();
+
+    // Each block has to be held until its end is found, because the code at
+    // the start depends on its length
+    let mut block = [0u8; MAX_BLOCK];
+    let mut len = 0;
+    let bytes = core::iter::once(&channel.0).chain(payload).chain(crc.iter());
+    for &byte in bytes {
+        if byte == 0 {
+            flush(&block[..len], &mut write);
+            len = 0;
+            continue;
+        }
+        block[len] = byte;
+        len += 1;
+        if len == MAX_BLOCK {
+            // A full block has an implied end, rather than a zero
+            write(0xFF);
+            block.iter().for_each(|&b| write(b));
+            len = 0;
+        }
+    }
+    flush(&block[..len], &mut write);
+    write(0);
+}
+
+/// Write a block which ended with a zero (or the end of the frame)
+fn flush(block: &[u8], write: &mut impl FnMut(u8)) {
+    write(block.len() as u8 + 1);
+    block.iter().for_each(|&b| write(b));
+}
+
+/// Why a frame couldn't be decoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// The frame ended part way through a block
+    Truncated,
+    /// The frame is too short to have a channel and CRC
+    TooShort,
+    /// The frame didn't fit in the decoder's buffer
+    TooLong,
+    /// The CRC doesn't match, so the frame was corrupted
+    BadCrc,
+}
+
+/// A frame that has been decoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub channel: Channel,
+    pub payload: &'a [u8],
+}
+
+/// Decodes frames one byte at a time, as they arrive. `N` is the largest
+/// frame that can be decoded, including the channel and CRC.
+pub struct Decoder<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    /// The code of the current block
+    code: u8,
+    /// The number of data bytes left in the current block
+    remaining: u8,
+    overflowed: bool,
+}
+
+impl<const N: usize> Decoder<N> {
+    pub const fn new() -> Decoder<N> {
+        Decoder {
+            buf: [0; N],
+            len: 0,
+            code: 0,
+            remaining: 0,
+            overflowed: false,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len < N {
+            self.buf[self.len] = byte;
+            self.len += 1;
+        } else {
+            self.overflowed = true;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.len = 0;
+        self.code = 0;
+        self.remaining = 0;
+        self.overflowed = false;
+    }
+
+    /// Process a received byte, and return the frame if it was the last byte
+    /// of one. Nothing is returned for the empty frames between two zeros,
+    /// which can be sent to make sure the receiver is in sync.
+    pub fn feed(&mut self, byte: u8) -> Option<Result<Frame<'_>, FrameError>> {
+        if byte != 0 {
+            if self.remaining > 0 {
+                self.push(byte);
+                self.remaining -= 1;
+            } else {
+                // A new block, so the previous one ended with a zero unless
+                // it was full
+                if self.code != 0 && self.code != 0xFF {
+                    self.push(0);
+                }
+                self.code = byte;
+                self.remaining = byte - 1;
+            }
+            return None;
+        }
+
+        let (code, remaining, len, overflowed) = (self.code, self.remaining, self.len, self.overflowed);
+        self.reset();
+        if code == 0 {
+            return None;
+        }
+        Some(if remaining > 0 {
+            Err(FrameError::Truncated)
+        } else if overflowed {
+            Err(FrameError::TooLong)
+        } else if len < OVERHEAD {
+            Err(FrameError::TooShort)
+        } else {
+            let (data, crc) = self.buf[..len].split_at(len - 4);
+            let mut expected = Crc32::new();
+            expected.update(data);
+            if expected.finish().to_le_bytes() != crc {
+                Err(FrameError::BadCrc)
+            } else {
+                Ok(Frame {
+                    channel: Channel(data[0]),
+                    payload: &data[1..],
+                })
+            }
+        })
+    }
+}
+
+impl<const N: usize> Default for Decoder<N> {
+    fn default() -> Decoder<N> {
+        Decoder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::vec::Vec;
+
+    fn encoded(channel: Channel, payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode(channel, payload, |b| out.push(b));
+        out
+    }
+
+    /// Decode everything, returning the frames (or errors) with owned payloads
+    fn decode_all(bytes: &[u8]) -> Vec<Result<(Channel, Vec<u8>), FrameError>> {
+        let mut decoder = Decoder::<1024>::new();
+        bytes.iter()
+            .filter_map(|&b| decoder.feed(b).map(|r| r.map(|f| (f.channel, f.payload.to_vec()))))
+            .collect()
+    }
+
+    #[test]
+    pub fn round_trip() {
+        let long: Vec<u8> = (0..800).map(|i| (i % 251) as u8).collect();
+        let non_zero: Vec<u8> = (0..600).map(|i| (i % 255) as u8 + 1).collect();
+        let mut payloads: Vec<Vec<u8>> = std::vec![
+            Vec::new(),
+            std::vec![0],
+            std::vec![0, 0, 0],
+            b"hello\0world".to_vec(),
+            long,
+        ];
+        // Payloads around the block size, with no zeros to split them
+        for len in 240..270 {
+            payloads.push(non_zero[..len].to_vec());
+        }
+
+        for payload in payloads.iter() {
+            let frame = encoded(Channel::FILE, payload);
+            assert!(frame.len() <= max_encoded_len(payload.len()), "{} bytes", payload.len());
+            assert_eq!(frame.iter().position(|&b| b == 0), Some(frame.len() - 1));
+            assert_eq!(decode_all(&frame), [Ok((Channel::FILE, payload.clone()))], "{} bytes", payload.len());
+        }
+    }
+
+    #[test]
+    pub fn recovers_from_corruption() {
+        let first = encoded(Channel::CONSOLE, b"first");
+        let second = encoded(Channel::LOG, b"second");
+
+        // A flipped bit is caught by the CRC
+        let mut corrupt = first.clone();
+        corrupt[3] ^= 0x10;
+        assert_eq!(decode_all(&corrupt), [Err(FrameError::BadCrc)]);
+
+        // Garbage and lost bytes only affect the frame they're in
+        let mut stream = std::vec![0x12, 0x34, 0];
+        stream.extend_from_slice(&first[..4]);
+        stream.push(0);
+        stream.extend_from_slice(&second);
+        stream.extend_from_slice(&[0, 0]);
+        stream.extend_from_slice(&first);
+        assert_eq!(decode_all(&stream), [
+            Err(FrameError::Truncated),
+            Err(FrameError::Truncated),
+            Ok((Channel::LOG, b"second".to_vec())),
+            Ok((Channel::CONSOLE, b"first".to_vec())),
+        ]);
+
+        let mut small = Decoder::<8>::new();
+        let results: Vec<_> = second.iter().filter_map(|&b| small.feed(b).map(|r| r.map(|_| ()))).collect();
+        assert_eq!(results, [Err(FrameError::TooLong)]);
+        assert_eq!(decode_all(&[2, 1, 0]), [Err(FrameError::TooShort)]);
+    }
+}
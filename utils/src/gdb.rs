@@ -0,0 +1,442 @@
+//! The packet layer of the GDB remote serial protocol, and parsing of the
+//! commands that a simple stub needs to support.
+//!
+//! Packets look like `$data#cs`, where `cs` is the modulo 256 sum of the data
+//! as two hex digits. The receiver acknowledges each packet with `+`, or asks
+//! for it again with `-`. A lone 0x03 byte asks the target to stop.
+
+use core::fmt;
+use core::str;
+
+/// Sent by GDB outside of a packet to interrupt the target
+const INTERRUPT: u8 = 0x03;
+/// Characters in a packet that has this after them are XORed with 0x20
+const ESCAPE: u8 = b'}';
+
+/// The modulo 256 sum of the bytes in a packet
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Parse a big endian hex number, like the addresses and lengths in commands
+pub fn parse_hex(s: &[u8]) -> Option<u64> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+    s.iter().try_fold(0u64, |n, &c| Some((n << 4) | u64::from(hex_digit(c)?)))
+}
+
+/// Decode pairs of hex digits into bytes, returning None if `hex` isn't twice
+/// as long as `out` or isn't valid hex
+pub fn decode_hex(hex: &[u8], out: &mut [u8]) -> Option<()> {
+    if hex.len() != out.len() * 2 {
+        return None;
+    }
+    for (byte, pair) in out.iter_mut().zip(hex.chunks(2)) {
+        *byte = (hex_digit(pair[0])? << 4) | hex_digit(pair[1])?;
+    }
+    Some(())
+}
+
+/// Something received by a `PacketReader`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Received {
+    /// A whole packet has arrived, and is available from `packet()`. It
+    /// should be acknowledged with `+`.
+    Packet,
+    /// A packet arrived with the wrong checksum, or was too long, and should
+    /// be rejected with `-`
+    Corrupt,
+    /// GDB wants the target to stop
+    Interrupt,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ReadState {
+    /// Waiting for the `$` that starts a packet
+    Idle,
+    Data,
+    /// Received the escape character in the data
+    Escape,
+    /// Received the `#`, and some of the checksum digits
+    Checksum(u8, u8),
+}
+
+/// Collects packets from the bytes received from GDB
+pub struct PacketReader<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    overflowed: bool,
+    /// The sum of the bytes as they were sent, before unescaping
+    sum: u8,
+    state: ReadState,
+}
+
+impl<const N: usize> PacketReader<N> {
+    pub const fn new() -> PacketReader<N> {
+        PacketReader {
+            buf: [0; N],
+            len: 0,
+            overflowed: false,
+            sum: 0,
+            state: ReadState::Idle,
+        }
+    }
+
+    /// Forget any packet that was partly received, so that the next one
+    /// starts afresh
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.state = ReadState::Idle;
+    }
+
+    /// The data of the last packet that was received
+    pub fn packet(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len < N {
+            self.buf[self.len] = byte;
+            self.len += 1;
+        } else {
+            self.overflowed = true;
+        }
+    }
+
+    /// Process a byte from GDB
+    pub fn feed(&mut self, byte: u8) -> Option<Received> {
+        match self.state {
+            ReadState::Idle => match byte {
+                b'$' => {
+                    self.len = 0;
+                    self.overflowed = false;
+                    self.sum = 0;
+                    self.state = ReadState::Data;
+                },
+                INTERRUPT => return Some(Received::Interrupt),
+                // Acknowledgements for what we sent, and line noise
+                _ => (),
+            },
+            ReadState::Data | ReadState::Escape if byte == b'#' => {
+                self.state = ReadState::Checksum(0, 0);
+            },
+            ReadState::Data => {
+                self.sum = self.sum.wrapping_add(byte);
+                if byte == ESCAPE {
+                    self.state = ReadState::Escape;
+                } else {
+                    self.push(byte);
+                }
+            },
+            ReadState::Escape => {
+                self.sum = self.sum.wrapping_add(byte);
+                self.push(byte ^ 0x20);
+                self.state = ReadState::Data;
+            },
+            ReadState::Checksum(0, _) => {
+                self.state = ReadState::Checksum(1, hex_digit(byte).unwrap_or(0xFF));
+            },
+            ReadState::Checksum(_, high) => {
+                self.state = ReadState::Idle;
+                let valid = match (high, hex_digit(byte)) {
+                    (0..=15, Some(low)) => (high << 4) | low == self.sum,
+                    _ => false,
+                };
+                return Some(if valid && !self.overflowed { Received::Packet } else { Received::Corrupt });
+            },
+        }
+        None
+    }
+}
+
+impl<const N: usize> Default for PacketReader<N> {
+    fn default() -> PacketReader<N> {
+        PacketReader::new()
+    }
+}
+
+/// A breakpoint or watchpoint type from a `Z` or `z` packet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakpointType {
+    Software,
+    Hardware,
+    WriteWatchpoint,
+    ReadWatchpoint,
+    AccessWatchpoint,
+}
+
+/// The commands that the stub understands. Anything else should get an
+/// empty response, which tells GDB that it isn't supported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command<'a> {
+    /// `?`: why the target stopped
+    HaltReason,
+    /// `g`
+    ReadRegisters,
+    /// `G`, with the register values in hex
+    WriteRegisters(&'a [u8]),
+    /// `p n`
+    ReadRegister(usize),
+    /// `P n=value`, with the value in hex
+    WriteRegister(usize, &'a [u8]),
+    /// `m addr,length`
+    ReadMemory { address: u64, length: usize },
+    /// `M addr,length:data`, with the data in hex
+    WriteMemory { address: u64, data: &'a [u8] },
+    /// `c [addr]`
+    Continue(Option<u64>),
+    /// `s [addr]`
+    Step(Option<u64>),
+    /// `Z type,addr,kind`
+    InsertBreakpoint(BreakpointType, u64),
+    /// `z type,addr,kind`
+    RemoveBreakpoint(BreakpointType, u64),
+    /// `qSupported`
+    QuerySupported,
+    /// `QStartNoAckMode`
+    StartNoAckMode,
+    /// `D`
+    Detach,
+    /// `k`
+    Kill,
+    Unsupported,
+}
+
+impl<'a> Command<'a> {
+    pub fn parse(packet: &'a [u8]) -> Command<'a> {
+        Command::try_parse(packet).unwrap_or(Command::Unsupported)
+    }
+
+    fn try_parse(packet: &'a [u8]) -> Option<Command<'a>> {
+        let (&first, args) = packet.split_first()?;
+        let optional_address = || if args.is_empty() { Some(None) } else { parse_hex(args).map(Some) };
+        Some(match first {
+            b'?' => Command::HaltReason,
+            b'g' => Command::ReadRegisters,
+            b'G' => Command::WriteRegisters(args),
+            b'p' => Command::ReadRegister(parse_hex(args)? as usize),
+            b'P' => {
+                let (register, value) = split(args, b'=')?;
+                Command::WriteRegister(parse_hex(register)? as usize, value)
+            },
+            b'm' => {
+                let (address, length) = split(args, b',')?;
+                Command::ReadMemory { address: parse_hex(address)?, length: parse_hex(length)? as usize }
+            },
+            b'M' => {
+                let (address, rest) = split(args, b',')?;
+                let (length, data) = split(rest, b':')?;
+                if parse_hex(length)? as usize * 2 != data.len() {
+                    return None;
+                }
+                Command::WriteMemory { address: parse_hex(address)?, data }
+            },
+            b'c' => Command::Continue(optional_address()?),
+            b's' => Command::Step(optional_address()?),
+            b'Z' | b'z' => {
+                let (kind, rest) = split(args, b',')?;
+                let (address, _size) = split(rest, b',')?;
+                let kind = match kind {
+                    b"0" => BreakpointType::Software,
+                    b"1" => BreakpointType::Hardware,
+                    b"2" => BreakpointType::WriteWatchpoint,
+                    b"3" => BreakpointType::ReadWatchpoint,
+                    b"4" => BreakpointType::AccessWatchpoint,
+                    _ => return None,
+                };
+                if first == b'Z' {
+                    Command::InsertBreakpoint(kind, parse_hex(address)?)
+                } else {
+                    Command::RemoveBreakpoint(kind, parse_hex(address)?)
+                }
+            },
+            b'q' if args.starts_with(b"Supported") => Command::QuerySupported,
+            b'Q' if args == b"StartNoAckMode" => Command::StartNoAckMode,
+            b'D' => Command::Detach,
+            b'k' => Command::Kill,
+            _ => Command::Unsupported,
+        })
+    }
+}
+
+/// Split at the first `separator`
+fn split(s: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let i = s.iter().position(|&c| c == separator)?;
+    Some((&s[..i], &s[i + 1..]))
+}
+
+/// Builds a response packet. Text can be added with `write!`, and is escaped
+/// as needed when the packet is sent.
+pub struct Response<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Response<N> {
+    pub const fn new() -> Response<N> {
+        Response {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// The unescaped contents of the packet
+    pub fn data(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Add bytes as pairs of hex digits
+    pub fn push_hex(&mut self, bytes: &[u8]) -> fmt::Result {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        if self.len + bytes.len() * 2 > N {
+            return Err(fmt::Error);
+        }
+        for &byte in bytes {
+            self.buf[self.len] = DIGITS[usize::from(byte >> 4)];
+            self.buf[self.len + 1] = DIGITS[usize::from(byte & 0xF)];
+            self.len += 2;
+        }
+        Ok(())
+    }
+
+    /// Send the packet, escaping any characters that would confuse GDB
+    pub fn send(&self, mut write_byte: impl FnMut(u8)) {
+        write_byte(b'$');
+        let mut sum = 0u8;
+        for &byte in self.data() {
+            let escaped = [ESCAPE, byte ^ 0x20];
+            let bytes: &[u8] = match byte {
+                b'$' | b'#' | b'}' | b'*' => &escaped,
+                _ => core::slice::from_ref(&byte),
+            };
+            for &b in bytes {
+                sum = sum.wrapping_add(b);
+                write_byte(b);
+            }
+        }
+        write_byte(b'#');
+        let mut digits = Response::<2>::new();
+        let _ = digits.push_hex(&[sum]);
+        for &b in digits.data() {
+            write_byte(b);
+        }
+    }
+}
+
+impl<const N: usize> Default for Response<N> {
+    fn default() -> Response<N> {
+        Response::new()
+    }
+}
+
+impl<const N: usize> fmt::Write for Response<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.len + s.len() > N {
+            return Err(fmt::Error);
+        }
+        self.buf[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+impl<const N: usize> fmt::Debug for Response<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match str::from_utf8(self.data()) {
+            Ok(text) => write!(f, "Response({:?})", text),
+            Err(_) => write!(f, "Response({:?})", self.data()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::fmt::Write;
+    use std::vec::Vec;
+
+    fn read_all<const N: usize>(reader: &mut PacketReader<N>, bytes: &[u8]) -> Vec<Received> {
+        bytes.iter().filter_map(|&b| reader.feed(b)).collect()
+    }
+
+    #[test]
+    pub fn reads_packets() {
+        let mut reader = PacketReader::<64>::new();
+        assert_eq!(read_all(&mut reader, b"+$m80000,4#c5"), [Received::Packet]);
+        assert_eq!(reader.packet(), b"m80000,4");
+        assert_eq!(Command::parse(reader.packet()), Command::ReadMemory { address: 0x80000, length: 4 });
+
+        // Bad checksums and interrupts
+        assert_eq!(read_all(&mut reader, b"$g#00\x03"), [Received::Corrupt, Received::Interrupt]);
+
+        // Escaped characters are counted in the checksum as they were sent
+        let sent = b"M0,1:}]";
+        let packet = std::format!("${}#{:02x}", str::from_utf8(sent).unwrap(), checksum(sent));
+        assert_eq!(read_all(&mut reader, packet.as_bytes()), [Received::Packet]);
+        assert_eq!(reader.packet(), b"M0,1:}");
+
+        // A packet that was cut off is forgotten when the reader is reset
+        assert_eq!(read_all(&mut reader, b"$m8"), []);
+        reader.reset();
+        assert_eq!(read_all(&mut reader, b"$g#67"), [Received::Packet]);
+        assert_eq!(reader.packet(), b"g");
+
+        // Packets that don't fit are rejected
+        let mut small = PacketReader::<2>::new();
+        assert_eq!(read_all(&mut small, b"$abc#26"), [Received::Corrupt]);
+    }
+
+    #[test]
+    pub fn parses_commands() {
+        assert_eq!(Command::parse(b"?"), Command::HaltReason);
+        assert_eq!(Command::parse(b"p20"), Command::ReadRegister(32));
+        assert_eq!(Command::parse(b"P1f=0010000000000000"), Command::WriteRegister(31, b"0010000000000000"));
+        assert_eq!(Command::parse(b"M1000,2:abcd"), Command::WriteMemory { address: 0x1000, data: b"abcd" });
+        assert_eq!(Command::parse(b"M1000,2:abc"), Command::Unsupported);
+        assert_eq!(Command::parse(b"c"), Command::Continue(None));
+        assert_eq!(Command::parse(b"s80004"), Command::Step(Some(0x80004)));
+        assert_eq!(Command::parse(b"Z0,80010,4"), Command::InsertBreakpoint(BreakpointType::Software, 0x80010));
+        assert_eq!(Command::parse(b"z0,80010,4"), Command::RemoveBreakpoint(BreakpointType::Software, 0x80010));
+        assert_eq!(Command::parse(b"qSupported:multiprocess+"), Command::QuerySupported);
+        assert_eq!(Command::parse(b"vMustReplyEmpty"), Command::Unsupported);
+        assert_eq!(Command::parse(b""), Command::Unsupported);
+    }
+
+    #[test]
+    pub fn sends_responses() {
+        let mut response = Response::<32>::new();
+        write!(response, "T05").unwrap();
+        response.push_hex(&[0xDE, 0xAD]).unwrap();
+        let mut sent = Vec::new();
+        response.send(|b| sent.push(b));
+        assert_eq!(sent, b"$T05dead#47");
+
+        // Special characters are escaped
+        response.clear();
+        write!(response, "a#b").unwrap();
+        sent.clear();
+        response.send(|b| sent.push(b));
+        let mut reader = PacketReader::<32>::new();
+        assert_eq!(read_all(&mut reader, &sent), [Received::Packet]);
+        assert_eq!(reader.packet(), b"a#b");
+
+        let mut buf = [0; 2];
+        assert_eq!(decode_hex(b"dEaD", &mut buf), Some(()));
+        assert_eq!(buf, [0xDE, 0xAD]);
+        assert_eq!(decode_hex(b"dEa", &mut buf), None);
+    }
+}
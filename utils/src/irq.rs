@@ -0,0 +1,43 @@
+//! Helpers for masking and unmasking interrupts on the current core
+
+/// The value of the DAIF register before interrupts were masked, which needs
+/// to be passed back to `restore` to undo the masking
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SavedDaif(u64);
+
+/// Mask IRQs on this core, and return the previous interrupt state so that
+/// it can be restored later
+#[cfg(target_arch = "aarch64")]
+#[inline]
+pub fn save_and_disable() -> SavedDaif {
+    let daif: u64;
+    unsafe {
+        asm!("MRS     $0, DAIF
+              MSR     DAIFSET, #2" : "=r"(daif) ::: "memory" : "volatile");
+    }
+    SavedDaif(daif)
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+#[inline]
+pub fn save_and_disable() -> SavedDaif {
+    // There are no interrupts to mask when running on the host
+    SavedDaif(0)
+}
+
+/// Restore the interrupt state that was returned by `save_and_disable`.
+///
+/// Nested masks must be restored in the reverse order to that in which they
+/// were saved, otherwise interrupts could be re-enabled too early.
+#[cfg(target_arch = "aarch64")]
+#[inline]
+pub fn restore(saved: SavedDaif) {
+    unsafe {
+        asm!("MSR     DAIF, $0" :: "r"(saved.0) : "memory" : "volatile");
+    }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+#[inline]
+pub fn restore(_saved: SavedDaif) {
+}
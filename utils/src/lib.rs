@@ -7,4 +7,26 @@
 #[cfg(test)]
 extern crate std;
 
-pub mod sync;
\ No newline at end of file
+pub mod backtrace;
+pub mod baud_rate;
+pub mod block_transfer;
+pub mod cpu;
+pub mod crc;
+pub mod framing;
+pub mod gdb;
+pub mod irq;
+pub mod line_editor;
+pub mod log_buffer;
+// These rely on const constructors, which aren't available under loom
+#[cfg(not(loom))]
+pub mod percpu;
+#[cfg(not(loom))]
+pub mod spsc;
+pub mod sha256;
+pub mod signed_image;
+pub mod symbols;
+pub mod sync;
+pub mod utf8;
+
+#[cfg(all(loom, feature = "lock-debug"))]
+compile_error!("lock-debug can't be used when model checking with loom");
\ No newline at end of file
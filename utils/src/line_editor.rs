@@ -0,0 +1,509 @@
+//! An interactive line editor for VT100-compatible terminals.
+//!
+//! The editor is fed one character at a time and writes whatever is needed
+//! to keep the terminal in sync with the line being edited, so it doesn't
+//! depend on any particular device. It supports cursor movement, deleting
+//! characters and words, history, and tab completion.
+//!
+//! Only printable ASCII can be entered into the line.
+
+use core::fmt::{self, Write};
+
+const CTRL_A: char = '\x01';
+const CTRL_C: char = '\x03';
+const CTRL_E: char = '\x05';
+const BACKSPACE: char = '\x08';
+const TAB: char = '\t';
+const CTRL_U: char = '\x15';
+const CTRL_W: char = '\x17';
+const ESCAPE: char = '\x1b';
+const DELETE: char = '\x7f';
+
+/// Something that happened as a result of feeding a character to the editor
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Enter was pressed, and the line is available from `line()`
+    Line,
+    /// Ctrl-C was pressed, and the line has been discarded
+    Interrupt,
+}
+
+/// Provides tab completion for the editor
+pub trait Completer {
+    /// Call `candidate` with each possible completion of the last word in
+    /// `line`, which is the part of the line before the cursor. Each
+    /// candidate replaces the whole of the last word.
+    fn complete(&self, line: &str, candidate: &mut dyn FnMut(&str));
+}
+
+impl<F: Fn(&str, &mut dyn FnMut(&str))> Completer for F {
+    fn complete(&self, line: &str, candidate: &mut dyn FnMut(&str)) {
+        self(line, candidate)
+    }
+}
+
+/// A completer that never suggests anything
+pub struct NoCompletion;
+
+impl Completer for NoCompletion {
+    fn complete(&self, _line: &str, _candidate: &mut dyn FnMut(&str)) {
+    }
+}
+
+/// Where we are in parsing an escape sequence
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum EscapeState {
+    None,
+    /// Received ESC
+    Escape,
+    /// Received ESC [, followed by an optional numeric parameter
+    Csi(u8),
+    /// Received ESC O
+    Ss3,
+}
+
+/// A fixed-size line of text
+#[derive(Clone, Copy)]
+struct Line<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Line<N> {
+    const fn new() -> Line<N> {
+        Line {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // Only ASCII is ever inserted
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+/// A line editor holding lines of up to `N` characters, and remembering the
+/// last `H` lines that were entered
+pub struct LineEditor<const N: usize, const H: usize> {
+    prompt: &'static str,
+    line: Line<N>,
+    cursor: usize,
+    escape: EscapeState,
+    /// Whether the last character was a carriage return, so that the line
+    /// feed of a CRLF pair doesn't submit another (empty) line
+    after_cr: bool,
+    /// The lines entered previously, oldest first
+    history: [Line<N>; H],
+    history_len: usize,
+    /// The history entry being shown, or None if editing a new line
+    history_index: Option<usize>,
+    /// The new line that was being edited before browsing the history
+    draft: Line<N>,
+}
+
+impl<const N: usize, const H: usize> LineEditor<N, H> {
+    /// Construct an editor which shows `prompt` before each line
+    pub const fn new(prompt: &'static str) -> LineEditor<N, H> {
+        LineEditor {
+            prompt,
+            line: Line::new(),
+            cursor: 0,
+            escape: EscapeState::None,
+            after_cr: false,
+            history: [Line::new(); H],
+            history_len: 0,
+            history_index: None,
+            draft: Line::new(),
+        }
+    }
+
+    /// Clear the line and show the prompt, ready to read a new line
+    pub fn start<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        self.line.len = 0;
+        self.cursor = 0;
+        self.escape = EscapeState::None;
+        self.history_index = None;
+        out.write_str(self.prompt)
+    }
+
+    /// The text of the line being edited. After `Event::Line`, this is the
+    /// line that was entered.
+    pub fn line(&self) -> &str {
+        self.line.as_str()
+    }
+
+    /// Whether nothing has been entered yet
+    pub fn is_empty(&self) -> bool {
+        self.line.len == 0
+    }
+
+    /// Handle a character received from the terminal, writing any updates to
+    /// the display to `out`
+    pub fn feed<W: Write, C: Completer + ?Sized>(&mut self, c: char, out: &mut W, completer: &C)
+        -> Result<Option<Event>, fmt::Error>
+    {
+        let after_cr = self.after_cr;
+        self.after_cr = c == '\r';
+
+        match self.escape {
+            EscapeState::None => (),
+            EscapeState::Escape => {
+                self.escape = match c {
+                    '[' => EscapeState::Csi(0),
+                    'O' => EscapeState::Ss3,
+                    _ => EscapeState::None,
+                };
+                return Ok(None);
+            },
+            EscapeState::Csi(param) => {
+                if let Some(digit) = c.to_digit(10) {
+                    self.escape = EscapeState::Csi(param.saturating_mul(10).saturating_add(digit as u8));
+                    return Ok(None);
+                }
+                self.escape = EscapeState::None;
+                match (c, param) {
+                    ('A', _) => self.history_previous(out)?,
+                    ('B', _) => self.history_next(out)?,
+                    ('C', count) => self.move_to((self.cursor + usize::from(count.max(1))).min(self.line.len), out)?,
+                    ('D', count) => self.move_to(self.cursor.saturating_sub(usize::from(count.max(1))), out)?,
+                    ('H', _) | ('~', 1) | ('~', 7) => self.move_to(0, out)?,
+                    ('F', _) | ('~', 4) | ('~', 8) => self.move_to(self.line.len, out)?,
+                    ('~', 3) => self.delete_forward(out)?,
+                    _ => (),
+                }
+                return Ok(None);
+            },
+            EscapeState::Ss3 => {
+                self.escape = EscapeState::None;
+                match c {
+                    'A' => self.history_previous(out)?,
+                    'B' => self.history_next(out)?,
+                    'C' => self.move_right(out)?,
+                    'D' => self.move_left(out)?,
+                    'H' => self.move_to(0, out)?,
+                    'F' => self.move_to(self.line.len, out)?,
+                    _ => (),
+                }
+                return Ok(None);
+            },
+        }
+
+        match c {
+            '\r' => return self.submit(out),
+            '\n' if after_cr => (),
+            '\n' => return self.submit(out),
+            ESCAPE => self.escape = EscapeState::Escape,
+            CTRL_C => {
+                out.write_str("^C\r\n")?;
+                self.line.len = 0;
+                self.cursor = 0;
+                return Ok(Some(Event::Interrupt));
+            },
+            CTRL_A => self.move_to(0, out)?,
+            CTRL_E => self.move_to(self.line.len, out)?,
+            BACKSPACE | DELETE => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.remove(self.cursor, self.cursor + 1);
+                    self.refresh(out)?;
+                }
+            },
+            CTRL_U => {
+                self.remove(0, self.cursor);
+                self.cursor = 0;
+                self.refresh(out)?;
+            },
+            CTRL_W => {
+                let start = self.previous_word_start();
+                self.remove(start, self.cursor);
+                self.cursor = start;
+                self.refresh(out)?;
+            },
+            TAB => self.complete(out, completer)?,
+            // The cursor is tracked in bytes, and each byte is assumed to take
+            // up one column on the terminal, so anything other than printable
+            // ASCII (including decoded non-ASCII characters) is ignored
+            c if c.is_ascii_graphic() || c == ' ' => {
+                let mut buf = [0u8; 1];
+                self.insert(c.encode_utf8(&mut buf), out)?;
+            },
+            _ => (),
+        }
+        Ok(None)
+    }
+
+    /// Finish the current line and add it to the history
+    fn submit<W: Write>(&mut self, out: &mut W) -> Result<Option<Event>, fmt::Error> {
+        out.write_str("\r\n")?;
+        self.history_index = None;
+        if H > 0 && self.line.len > 0
+            && (self.history_len == 0 || self.history[self.history_len - 1].as_str() != self.line.as_str())
+        {
+            if self.history_len == H {
+                self.history.copy_within(1.., 0);
+                self.history_len -= 1;
+            }
+            self.history[self.history_len] = self.line;
+            self.history_len += 1;
+        }
+        Ok(Some(Event::Line))
+    }
+
+    /// Insert text at the cursor, if there's space for all of it
+    fn insert<W: Write>(&mut self, text: &str, out: &mut W) -> fmt::Result {
+        let end = self.line.len + text.len();
+        if end > N {
+            return Ok(());
+        }
+        self.line.buf.copy_within(self.cursor..self.line.len, self.cursor + text.len());
+        self.line.buf[self.cursor..self.cursor + text.len()].copy_from_slice(text.as_bytes());
+        self.line.len = end;
+        self.cursor += text.len();
+        if self.cursor == self.line.len {
+            // Appending, so there's nothing after it to redraw
+            out.write_str(text)
+        } else {
+            self.refresh(out)
+        }
+    }
+
+    /// Remove the characters in `start..end`, without moving the cursor
+    fn remove(&mut self, start: usize, end: usize) {
+        self.line.buf.copy_within(end..self.line.len, start);
+        self.line.len -= end - start;
+    }
+
+    fn delete_forward<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        if self.cursor < self.line.len {
+            self.remove(self.cursor, self.cursor + 1);
+            self.refresh(out)?;
+        }
+        Ok(())
+    }
+
+    /// The index of the start of the word before the cursor, skipping any
+    /// spaces between it and the cursor
+    fn previous_word_start(&self) -> usize {
+        let before = &self.line.buf[..self.cursor];
+        let end = before.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+        before[..end].iter().rposition(|&b| b == b' ').map_or(0, |i| i + 1)
+    }
+
+    fn move_left<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            out.write_str("\x1b[D")?;
+        }
+        Ok(())
+    }
+
+    fn move_right<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        if self.cursor < self.line.len {
+            self.cursor += 1;
+            out.write_str("\x1b[C")?;
+        }
+        Ok(())
+    }
+
+    fn move_to<W: Write>(&mut self, position: usize, out: &mut W) -> fmt::Result {
+        if position < self.cursor {
+            write!(out, "\x1b[{}D", self.cursor - position)?;
+        } else if position > self.cursor {
+            write!(out, "\x1b[{}C", position - self.cursor)?;
+        }
+        self.cursor = position;
+        Ok(())
+    }
+
+    fn history_previous<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        let index = match self.history_index {
+            None if self.history_len > 0 => {
+                self.draft = self.line;
+                self.history_len - 1
+            },
+            Some(index) if index > 0 => index - 1,
+            _ => return Ok(()),
+        };
+        self.history_index = Some(index);
+        self.line = self.history[index];
+        self.cursor = self.line.len;
+        self.refresh(out)
+    }
+
+    fn history_next<W: Write>(&mut self, out: &mut W) -> fmt::Result {
+        match self.history_index {
+            None => return Ok(()),
+            Some(index) if index + 1 < self.history_len => {
+                self.history_index = Some(index + 1);
+                self.line = self.history[index + 1];
+            },
+            Some(_) => {
+                self.history_index = None;
+                self.line = self.draft;
+            },
+        }
+        self.cursor = self.line.len;
+        self.refresh(out)
+    }
+
+    /// Complete the word before the cursor. If there's only one candidate it
+    /// is filled in, otherwise as much as the candidates have in common is
+    /// filled in, and if that doesn't add anything they're all listed.
+    fn complete<W: Write, C: Completer + ?Sized>(&mut self, out: &mut W, completer: &C) -> fmt::Result {
+        let word_start = self.previous_word_start();
+        if self.line.buf[word_start..self.cursor].contains(&b' ') {
+            // The cursor is after a space, so there's no word to complete
+            return Ok(());
+        }
+        let word_len = self.cursor - word_start;
+
+        let mut common = Line::<N>::new();
+        let mut count = 0;
+        completer.complete(&self.line.as_str()[..self.cursor], &mut |candidate| {
+            if count == 0 {
+                let len = candidate.len().min(N);
+                common.buf[..len].copy_from_slice(&candidate.as_bytes()[..len]);
+                common.len = len;
+            } else {
+                common.len = common.buf[..common.len].iter()
+                    .zip(candidate.bytes())
+                    .take_while(|(a, b)| *a == b)
+                    .count();
+            }
+            count += 1;
+        });
+
+        if count == 0 || common.len < word_len {
+            return Ok(());
+        }
+        // Reuse the common prefix as the text to insert, to keep the stack
+        // small
+        let extra = common.len - word_len;
+        common.buf.copy_within(word_len..common.len, 0);
+        common.len = extra;
+        if count == 1 && common.len < N {
+            common.buf[common.len] = b' ';
+            common.len += 1;
+        }
+
+        if common.len > 0 {
+            self.insert(common.as_str(), out)
+        } else {
+            // Nothing to add, so show the options
+            out.write_str("\r\n")?;
+            let mut result = Ok(());
+            completer.complete(&self.line.as_str()[..self.cursor], &mut |candidate| {
+                if result.is_ok() {
+                    result = write!(out, "{}  ", candidate);
+                }
+            });
+            result?;
+            out.write_str("\r\n")?;
+            self.refresh(out)
+        }
+    }
+
+    /// Redraw the prompt and the line, and put the cursor back where it was
+    fn refresh<W: Write>(&self, out: &mut W) -> fmt::Result {
+        write!(out, "\r{}{}\x1b[K", self.prompt, self.line.as_str())?;
+        if self.cursor < self.line.len {
+            write!(out, "\x1b[{}D", self.line.len - self.cursor)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::string::String;
+
+    type Editor = LineEditor<32, 4>;
+
+    /// Feed each character of `input` to the editor, returning the last event
+    fn feed<C: Completer>(editor: &mut Editor, input: &str, completer: &C) -> Option<Event> {
+        let mut out = String::new();
+        let mut event = None;
+        for c in input.chars() {
+            event = editor.feed(c, &mut out, completer).unwrap();
+        }
+        event
+    }
+
+    fn new_editor() -> Editor {
+        let mut editor = Editor::new("> ");
+        editor.start(&mut String::new()).unwrap();
+        editor
+    }
+
+    #[test]
+    pub fn cursor_movement_and_editing() {
+        let mut editor = new_editor();
+        // Type "helo", go left, insert "l", go to the start, delete forward,
+        // go to the end and backspace
+        feed(&mut editor, "helo\x1b[Dl\x1b[H\x1b[3~\x1bOFX\x7f", &NoCompletion);
+        assert_eq!(editor.line(), "ello");
+        assert_eq!(feed(&mut editor, "\r", &NoCompletion), Some(Event::Line));
+        // The line feed of a CRLF doesn't submit another line
+        assert_eq!(feed(&mut editor, "\n", &NoCompletion), None);
+        assert_eq!(editor.line(), "ello");
+    }
+
+    #[test]
+    pub fn non_ascii_is_ignored() {
+        let mut editor = new_editor();
+        feed(&mut editor, "caf\u{e9} \u{1f600}x\x1b[D\x7f", &NoCompletion);
+        assert_eq!(editor.line(), "cafx");
+    }
+
+    #[test]
+    pub fn kill_word_and_line() {
+        let mut editor = new_editor();
+        feed(&mut editor, "one two  three  \x17", &NoCompletion);
+        assert_eq!(editor.line(), "one two  ");
+        feed(&mut editor, "\x1b[1~\x1b[4C\x15", &NoCompletion);
+        assert_eq!(editor.line(), "two  ");
+        assert_eq!(feed(&mut editor, "\x03", &NoCompletion), Some(Event::Interrupt));
+        assert!(editor.is_empty());
+    }
+
+    #[test]
+    pub fn history() {
+        let mut editor = new_editor();
+        for line in ["first\r", "second\r", "second\r", "third\r"].iter() {
+            feed(&mut editor, line, &NoCompletion);
+            editor.start(&mut String::new()).unwrap();
+        }
+        feed(&mut editor, "draft\x1b[A\x1b[A", &NoCompletion);
+        assert_eq!(editor.line(), "second");
+        // Duplicates aren't recorded, so this is the oldest entry
+        feed(&mut editor, "\x1b[A\x1b[A", &NoCompletion);
+        assert_eq!(editor.line(), "first");
+        feed(&mut editor, "\x1b[B\x1b[B\x1b[B", &NoCompletion);
+        assert_eq!(editor.line(), "draft");
+    }
+
+    #[test]
+    pub fn tab_completion() {
+        let completer = |line: &str, candidate: &mut dyn FnMut(&str)| {
+            for name in ["reboot", "rand", "random"].iter() {
+                if name.starts_with(line) {
+                    candidate(name);
+                }
+            }
+        };
+        let mut editor = new_editor();
+        feed(&mut editor, "ra\t", &completer);
+        assert_eq!(editor.line(), "rand");
+        feed(&mut editor, "o\t", &completer);
+        assert_eq!(editor.line(), "random ");
+
+        let mut editor = new_editor();
+        let mut out = String::new();
+        editor.feed('r', &mut out, &completer).unwrap();
+        editor.feed('\t', &mut out, &completer).unwrap();
+        assert_eq!(editor.line(), "r");
+        assert!(out.contains("reboot  rand  random  "));
+    }
+}
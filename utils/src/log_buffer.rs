@@ -0,0 +1,108 @@
+//! A fixed-size buffer holding the most recent lines of a log.
+
+use core::fmt;
+
+/// A ring buffer of text which keeps the last `N` bytes written to it
+pub struct LogBuffer<const N: usize> {
+    buf: [u8; N],
+    /// The total number of bytes that have ever been written
+    written: usize,
+}
+
+impl<const N: usize> LogBuffer<N> {
+    /// Construct a new, empty buffer
+    pub const fn new() -> LogBuffer<N> {
+        LogBuffer {
+            buf: [0; N],
+            written: 0,
+        }
+    }
+
+    /// Append bytes to the buffer, overwriting the oldest ones if it's full
+    pub fn write(&mut self, bytes: &[u8]) {
+        if N == 0 {
+            return;
+        }
+        // Only the last N bytes can survive
+        let skipped = bytes.len().saturating_sub(N);
+        self.written += skipped;
+        let mut bytes = &bytes[skipped..];
+        while !bytes.is_empty() {
+            let start = self.written % N;
+            let len = bytes.len().min(N - start);
+            self.buf[start..start + len].copy_from_slice(&bytes[..len]);
+            self.written += len;
+            bytes = &bytes[len..];
+        }
+    }
+
+    /// The contents of the buffer, oldest first, split into two slices at
+    /// the point where it wraps around. Once old bytes have been overwritten,
+    /// the partial line at the start is left out.
+    pub fn contents(&self) -> (&[u8], &[u8]) {
+        if self.written <= N {
+            return (&self.buf[..self.written], &[]);
+        }
+        let start = self.written % N;
+        let (older, newer) = (&self.buf[start..], &self.buf[..start]);
+        match older.iter().position(|&b| b == b'\n') {
+            Some(i) => (&older[i + 1..], newer),
+            None => match newer.iter().position(|&b| b == b'\n') {
+                Some(i) => (&newer[i + 1..], &[]),
+                None => (&[], &[]),
+            },
+        }
+    }
+
+    /// Discard everything in the buffer
+    pub fn clear(&mut self) {
+        self.written = 0;
+    }
+}
+
+impl<const N: usize> Default for LogBuffer<N> {
+    fn default() -> LogBuffer<N> {
+        LogBuffer::new()
+    }
+}
+
+impl<const N: usize> fmt::Write for LogBuffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::vec::Vec;
+
+    fn contents<const N: usize>(buffer: &LogBuffer<N>) -> Vec<u8> {
+        let (older, newer) = buffer.contents();
+        [older, newer].concat()
+    }
+
+    #[test]
+    pub fn keeps_everything_until_full() {
+        let mut buffer = LogBuffer::<16>::new();
+        buffer.write(b"one\n");
+        buffer.write(b"two\n");
+        assert_eq!(contents(&buffer), b"one\ntwo\n");
+        buffer.clear();
+        assert_eq!(contents(&buffer), b"");
+    }
+
+    #[test]
+    pub fn drops_partial_lines_after_wrapping() {
+        let mut buffer = LogBuffer::<16>::new();
+        buffer.write(b"first line\n");
+        buffer.write(b"second\n");
+        buffer.write(b"third\n");
+        assert_eq!(contents(&buffer), b"second\nthird\n");
+
+        // A write bigger than the buffer keeps its end
+        buffer.write(b"a very long line\nend\n");
+        assert_eq!(contents(&buffer), b"end\n");
+    }
+}
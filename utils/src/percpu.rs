@@ -0,0 +1,173 @@
+//! Per-CPU variables, which hold a separate value for each core.
+//!
+//! Each core normally only touches its own value, with IRQs masked so that an
+//! interrupt handler on the same core can't observe it half-updated. Values
+//! for other cores can still be read with `for_each_cpu`, for example to add
+//! up statistics, so each value is protected by a reader-writer lock which is
+//! almost never contended.
+
+use crate::cpu::{self, MAX_CORES};
+use crate::irq::{self, SavedDaif};
+use crate::sync::{RwLock, ReadLockedRwLock, WriteLockedRwLock};
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+
+/// Declare one or more per-CPU statics, with each core's value starting out
+/// as the given (const) initialiser
+///
+/// ```ignore
+/// per_cpu! {
+///     static IRQ_DEPTH: u32 = 0;
+/// }
+/// ```
+#[macro_export]
+macro_rules! per_cpu {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr;)*) => {
+        $(
+            $(#[$attr])*
+            $vis static $name: $crate::percpu::PerCpu<$t> = {
+                #[allow(clippy::declare_interior_mutable_const)]
+                const SLOT: $crate::sync::RwLock<$t> = $crate::sync::RwLock::new($init);
+                $crate::percpu::PerCpu::from_slots([SLOT; $crate::cpu::MAX_CORES])
+            };
+        )*
+    };
+}
+
+/// A value with a separate copy for each core
+pub struct PerCpu<T> {
+    slots: [RwLock<T>; MAX_CORES],
+}
+
+impl<T> PerCpu<T> {
+    /// Construct a new per-CPU variable from the lock protecting each core's
+    /// value. Use `per_cpu!` rather than calling this directly.
+    #[doc(hidden)]
+    pub const fn from_slots(slots: [RwLock<T>; MAX_CORES]) -> PerCpu<T> {
+        PerCpu {
+            slots,
+        }
+    }
+
+    /// Get a reference to this core's value. IRQs are masked until the
+    /// reference is dropped.
+    pub fn get(&self) -> PerCpuRef<'_, T> {
+        let daif = irq::save_and_disable();
+        PerCpuRef {
+            guard: ManuallyDrop::new(self.slot().read()),
+            daif,
+        }
+    }
+
+    /// Get a mutable reference to this core's value. IRQs are masked until
+    /// the reference is dropped.
+    ///
+    /// This core must not already hold a reference to its value, otherwise
+    /// this will never return.
+    pub fn get_mut(&self) -> PerCpuRefMut<'_, T> {
+        let daif = irq::save_and_disable();
+        PerCpuRefMut {
+            guard: ManuallyDrop::new(self.slot().write()),
+            daif,
+        }
+    }
+
+    /// Call `f` with the index and value of each core in turn. Each core's
+    /// value is locked for reading while `f` is looking at it, so this must
+    /// not be called while this core holds a mutable reference to its value.
+    pub fn for_each_cpu<F: FnMut(usize, &T)>(&self, mut f: F) {
+        for (core, slot) in self.slots.iter().enumerate() {
+            let daif = irq::save_and_disable();
+            f(core, &slot.read());
+            irq::restore(daif);
+        }
+    }
+
+    /// Get the slot for the current core
+    fn slot(&self) -> &RwLock<T> {
+        let core = cpu::core_id();
+        assert!(core < MAX_CORES, "core {} has no per-CPU slot", core);
+        &self.slots[core]
+    }
+}
+
+/// A reference to the current core's value of a per-CPU variable
+pub struct PerCpuRef<'a, T> {
+    guard: ManuallyDrop<ReadLockedRwLock<'a, T>>,
+    daif: SavedDaif,
+}
+
+impl<'a, T> Drop for PerCpuRef<'a, T> {
+    fn drop(&mut self) {
+        unsafe {
+            ManuallyDrop::drop(&mut self.guard);
+        }
+        irq::restore(self.daif);
+    }
+}
+
+impl<'a, T> Deref for PerCpuRef<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.value()
+    }
+}
+
+/// A mutable reference to the current core's value of a per-CPU variable
+pub struct PerCpuRefMut<'a, T> {
+    guard: ManuallyDrop<WriteLockedRwLock<'a, T>>,
+    daif: SavedDaif,
+}
+
+impl<'a, T> Drop for PerCpuRefMut<'a, T> {
+    fn drop(&mut self) {
+        unsafe {
+            ManuallyDrop::drop(&mut self.guard);
+        }
+        irq::restore(self.daif);
+    }
+}
+
+impl<'a, T> Deref for PerCpuRefMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.value()
+    }
+}
+
+impl<'a, T> DerefMut for PerCpuRefMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.value_mut()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu;
+    use std::thread;
+
+    per_cpu! {
+        static COUNTER: u32 = 0;
+    }
+
+    #[test]
+    pub fn each_core_has_its_own_value() {
+        // Each thread pretends to be a different core
+        let threads: std::vec::Vec<_> = (0..2).map(|core| thread::spawn(move || {
+            cpu::set_test_core_id(core);
+            for _ in 0..10 {
+                *COUNTER.get_mut() += 1;
+            }
+            *COUNTER.get()
+        })).collect();
+        for t in threads {
+            assert_eq!(t.join().unwrap(), 10);
+        }
+
+        let mut total = 0;
+        COUNTER.for_each_cpu(|_, v| total += *v);
+        assert_eq!(total, 20);
+    }
+}
@@ -0,0 +1,180 @@
+//! The format of the kernel images that are sent to `self_update`, which lets
+//! the kernel check who they came from before running them.
+//!
+//! An image is a header followed by the kernel. The header is laid out as
+//! follows, with integers in little endian:
+//!
+//! | Offset | Size | Contents                                  |
+//! |--------|------|-------------------------------------------|
+//! | 0      | 4    | `MAGIC`                                   |
+//! | 4      | 4    | The format version, `VERSION`             |
+//! | 8      | 4    | The size of the kernel                    |
+//! | 12     | 32   | The SHA-256 digest of the kernel          |
+//! | 44     | 64   | The Ed25519 signature of the first 44 bytes |
+//!
+//! An unsigned image has a signature of all zeros.
+
+use crate::sha256::{self, DIGEST_LEN};
+use ed25519_compact::{KeyPair, PublicKey, Seed, Signature};
+
+pub const MAGIC: [u8; 4] = *b"KIMG";
+pub const VERSION: u32 = 1;
+/// The number of bytes before the kernel
+pub const HEADER_LEN: usize = SIGNED_LEN + SIGNATURE_LEN;
+/// The number of bytes in a public key, or in the seed of a secret key
+pub const KEY_LEN: usize = 32;
+
+/// The number of bytes at the start of the header that are signed
+const SIGNED_LEN: usize = 12 + DIGEST_LEN;
+const SIGNATURE_LEN: usize = 64;
+
+/// Which images can be accepted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Only accept images signed with the right key
+    SignedOnly,
+    /// Accept unsigned images too, which is only sensible for development
+    AllowUnsigned,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageError {
+    /// The image is too short to have a header
+    TooShort,
+    /// The image doesn't start with `MAGIC`
+    BadMagic,
+    /// The image is in a format that isn't understood
+    UnsupportedVersion(u32),
+    /// The size in the header doesn't match the kernel
+    WrongSize,
+    /// The kernel doesn't match the digest in the header
+    DigestMismatch,
+    /// The image isn't signed, and the policy doesn't allow that
+    Unsigned,
+    /// The image is signed, but there's no public key to check it with
+    NoPublicKey,
+    /// The signature is wrong, so the image wasn't signed with the right key
+    BadSignature,
+}
+
+/// An image whose header has been checked
+#[derive(Clone, Copy, Debug)]
+pub struct Image<'a> {
+    pub kernel: &'a [u8],
+    pub signed: bool,
+}
+
+/// Check that `image` is intact, and that it's signed with `public_key` if
+/// `policy` requires it
+pub fn verify<'a>(image: &'a [u8], public_key: Option<&[u8; KEY_LEN]>, policy: Policy) -> Result<Image<'a>, ImageError> {
+    if image.len() < HEADER_LEN {
+        return Err(ImageError::TooShort);
+    }
+    let (header, kernel) = image.split_at(HEADER_LEN);
+    if header[0..4] != MAGIC {
+        return Err(ImageError::BadMagic);
+    }
+    let version = read_u32(&header[4..8]);
+    if version != VERSION {
+        return Err(ImageError::UnsupportedVersion(version));
+    }
+    if read_u32(&header[8..12]) as usize != kernel.len() {
+        return Err(ImageError::WrongSize);
+    }
+    if header[12..SIGNED_LEN] != sha256::sha256(kernel) {
+        return Err(ImageError::DigestMismatch);
+    }
+
+    let signature = &header[SIGNED_LEN..];
+    if signature.iter().all(|&b| b == 0) {
+        return match policy {
+            Policy::AllowUnsigned => Ok(Image { kernel, signed: false }),
+            Policy::SignedOnly => Err(ImageError::Unsigned),
+        };
+    }
+    let public_key = PublicKey::new(*public_key.ok_or(ImageError::NoPublicKey)?);
+    let signature = Signature::from_slice(signature).map_err(|_| ImageError::BadSignature)?;
+    public_key.verify(&header[..SIGNED_LEN], &signature).map_err(|_| ImageError::BadSignature)?;
+    Ok(Image { kernel, signed: true })
+}
+
+/// Make the header for `kernel`, signing it with the secret key that's
+/// generated from `seed` if one is given
+pub fn header(kernel: &[u8], seed: Option<&[u8; KEY_LEN]>) -> [u8; HEADER_LEN] {
+    let mut header = [0; HEADER_LEN];
+    header[0..4].copy_from_slice(&MAGIC);
+    header[4..8].copy_from_slice(&VERSION.to_le_bytes());
+    header[8..12].copy_from_slice(&(kernel.len() as u32).to_le_bytes());
+    header[12..SIGNED_LEN].copy_from_slice(&sha256::sha256(kernel));
+    if let Some(seed) = seed {
+        let key_pair = KeyPair::from_seed(Seed::new(*seed));
+        let signature = key_pair.sk.sign(&header[..SIGNED_LEN], None);
+        header[SIGNED_LEN..].copy_from_slice(signature.as_ref());
+    }
+    header
+}
+
+/// The public key that goes with the secret key generated from `seed`
+pub fn public_key(seed: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    *KeyPair::from_seed(Seed::new(*seed)).pk
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::vec::Vec;
+
+    fn image(kernel: &[u8], seed: Option<&[u8; KEY_LEN]>) -> Vec<u8> {
+        let mut image = header(kernel, seed).to_vec();
+        image.extend_from_slice(kernel);
+        image
+    }
+
+    #[test]
+    pub fn checks_signatures() {
+        let seed = [7; KEY_LEN];
+        let key = public_key(&seed);
+        let other_key = public_key(&[8; KEY_LEN]);
+        let kernel: Vec<u8> = (0..3000).map(|i| (i % 256) as u8).collect();
+
+        let signed = image(&kernel, Some(&seed));
+        let verified = verify(&signed, Some(&key), Policy::SignedOnly).unwrap();
+        assert_eq!(verified.kernel, &kernel[..]);
+        assert!(verified.signed);
+        assert_eq!(verify(&signed, Some(&other_key), Policy::SignedOnly).unwrap_err(), ImageError::BadSignature);
+        assert_eq!(verify(&signed, None, Policy::AllowUnsigned).unwrap_err(), ImageError::NoPublicKey);
+
+        let unsigned = image(&kernel, None);
+        assert_eq!(verify(&unsigned, Some(&key), Policy::SignedOnly).unwrap_err(), ImageError::Unsigned);
+        let verified = verify(&unsigned, None, Policy::AllowUnsigned).unwrap();
+        assert_eq!(verified.kernel, &kernel[..]);
+        assert!(!verified.signed);
+
+        // Changing the header or the kernel invalidates the signature
+        let mut tampered = signed.clone();
+        tampered[HEADER_LEN + 10] ^= 1;
+        assert_eq!(verify(&tampered, Some(&key), Policy::SignedOnly).unwrap_err(), ImageError::DigestMismatch);
+        let mut tampered = image(&kernel[1..], None);
+        tampered[SIGNED_LEN..HEADER_LEN].copy_from_slice(&signed[SIGNED_LEN..HEADER_LEN]);
+        assert_eq!(verify(&tampered, Some(&key), Policy::SignedOnly).unwrap_err(), ImageError::BadSignature);
+    }
+
+    #[test]
+    pub fn rejects_malformed_images() {
+        let kernel = [1, 2, 3];
+        let good = image(&kernel, None);
+        assert_eq!(verify(&good[..HEADER_LEN - 1], None, Policy::AllowUnsigned).unwrap_err(), ImageError::TooShort);
+        assert_eq!(verify(&good[..HEADER_LEN + 2], None, Policy::AllowUnsigned).unwrap_err(), ImageError::WrongSize);
+
+        let mut bad = good.clone();
+        bad[0] = b'X';
+        assert_eq!(verify(&bad, None, Policy::AllowUnsigned).unwrap_err(), ImageError::BadMagic);
+        let mut bad = good;
+        bad[4] = 2;
+        assert_eq!(verify(&bad, None, Policy::AllowUnsigned).unwrap_err(), ImageError::UnsupportedVersion(2));
+    }
+}
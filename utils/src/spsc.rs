@@ -0,0 +1,323 @@
+//! A lock-free, fixed-capacity, single-producer/single-consumer queue.
+//!
+//! The queue can be shared between normal code and interrupt handlers (or
+//! between two cores) without taking a lock, as long as only one side ever
+//! pushes and only one side ever pops. That is enforced by splitting the ring
+//! into a `Producer` and a `Consumer` handle.
+
+use crate::sync::Once;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A ring buffer which can hold up to `N` values of type `T`
+pub struct SpscRing<T, const N: usize> {
+    /// The index of the next value to be popped. Only written by the consumer.
+    head: AtomicUsize,
+    /// The index of the next slot to be pushed to. Only written by the
+    /// producer.
+    tail: AtomicUsize,
+    /// Set once the handles have been given out by `try_split`
+    split: Once,
+    buffer: UnsafeCell<MaybeUninit<[T; N]>>,
+}
+
+impl<T, const N: usize> SpscRing<T, N> {
+    /// Construct a new, empty ring
+    pub const fn new() -> SpscRing<T, N> {
+        SpscRing {
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            split: Once::new(),
+            buffer: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// The maximum number of values that the ring can hold
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Split the ring into its producer and consumer handles
+    pub fn split(&mut self) -> (Producer<'_, T, N>, Consumer<'_, T, N>) {
+        (Producer { ring: self }, Consumer { ring: self })
+    }
+
+    /// Split a shared ring into its producer and consumer handles. This is
+    /// intended for rings held in statics, and only succeeds the first time
+    /// that it's called.
+    pub fn try_split(&self) -> Option<(Producer<'_, T, N>, Consumer<'_, T, N>)> {
+        let mut first = false;
+        self.split.call_once(|| first = true);
+        if first {
+            Some((Producer { ring: self }, Consumer { ring: self }))
+        } else {
+            None
+        }
+    }
+
+    /// Get a pointer to the slot for the given (unwrapped) index
+    fn slot(&self, index: usize) -> *mut T {
+        unsafe {
+            (self.buffer.get() as *mut T).add(index % N)
+        }
+    }
+}
+
+impl<T, const N: usize> Default for SpscRing<T, N> {
+    fn default() -> SpscRing<T, N> {
+        SpscRing::new()
+    }
+}
+
+impl<T, const N: usize> Drop for SpscRing<T, N> {
+    fn drop(&mut self) {
+        // Drop any values that were never popped
+        let tail = *self.tail.get_mut();
+        let mut head = *self.head.get_mut();
+        while head != tail {
+            unsafe {
+                ptr::drop_in_place(self.slot(head));
+            }
+            head = head.wrapping_add(1);
+        }
+    }
+}
+
+// Values are moved from the producer to the consumer, which could be on
+// another core, so T needs to be Send
+unsafe impl<T: Send, const N: usize> Sync for SpscRing<T, N> { }
+
+/// The handle used to push values into an `SpscRing`
+pub struct Producer<'a, T, const N: usize> {
+    ring: &'a SpscRing<T, N>,
+}
+
+impl<'a, T, const N: usize> Producer<'a, T, N> {
+    /// Push a value onto the ring, or give it back if the ring is full
+    pub fn push(&mut self, v: T) -> Result<(), T> {
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        // Acquire, so that the consumer has finished reading the slot before
+        // we overwrite it
+        let head = self.ring.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == N {
+            return Err(v);
+        }
+
+        unsafe {
+            self.ring.slot(tail).write(v);
+        }
+        // Release, so that the value is visible before the consumer sees it
+        self.ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// The number of values that can be pushed before the ring is full
+    pub fn free(&self) -> usize {
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        let head = self.ring.head.load(Ordering::Acquire);
+        N - tail.wrapping_sub(head)
+    }
+
+    /// Whether the ring is currently full
+    pub fn is_full(&self) -> bool {
+        self.free() == 0
+    }
+}
+
+impl<'a, T: Copy, const N: usize> Producer<'a, T, N> {
+    /// Push as many values from the start of `values` as will fit, and
+    /// return the number that were pushed. The consumer sees them all at once.
+    pub fn push_slice(&mut self, values: &[T]) -> usize {
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        let head = self.ring.head.load(Ordering::Acquire);
+        let count = values.len().min(N - tail.wrapping_sub(head));
+
+        for (i, v) in values[..count].iter().enumerate() {
+            unsafe {
+                self.ring.slot(tail.wrapping_add(i)).write(*v);
+            }
+        }
+        self.ring.tail.store(tail.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
+
+unsafe impl<'a, T: Send, const N: usize> Send for Producer<'a, T, N> { }
+
+/// The handle used to pop values from an `SpscRing`
+pub struct Consumer<'a, T, const N: usize> {
+    ring: &'a SpscRing<T, N>,
+}
+
+impl<'a, T, const N: usize> Consumer<'a, T, N> {
+    /// Pop the oldest value from the ring, or None if it's empty
+    pub fn pop(&mut self) -> Option<T> {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        // Acquire, so that the value written by the producer is visible
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+
+        let v = unsafe {
+            self.ring.slot(head).read()
+        };
+        // Release, so that we've finished reading before the producer can
+        // reuse the slot
+        self.ring.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(v)
+    }
+
+    /// The number of values waiting to be popped
+    pub fn len(&self) -> usize {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+
+    /// Whether there are no values waiting to be popped
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'a, T: Copy, const N: usize> Consumer<'a, T, N> {
+    /// Pop as many values as are available into the start of `values`, and
+    /// return the number that were popped
+    pub fn pop_slice(&mut self, values: &mut [T]) -> usize {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        let count = values.len().min(tail.wrapping_sub(head));
+
+        for (i, v) in values[..count].iter_mut().enumerate() {
+            *v = unsafe {
+                self.ring.slot(head.wrapping_add(i)).read()
+            };
+        }
+        self.ring.head.store(head.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
+
+unsafe impl<'a, T: Send, const N: usize> Send for Consumer<'a, T, N> { }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::rc::Rc;
+    use std::thread;
+
+    #[test]
+    pub fn push_and_pop_in_order() {
+        let mut ring: SpscRing<u32, 4> = SpscRing::new();
+        let (mut tx, mut rx) = ring.split();
+        assert_eq!(rx.pop(), None);
+        assert_eq!(tx.push(1), Ok(()));
+        assert_eq!(tx.push(2), Ok(()));
+        assert_eq!(rx.len(), 2);
+        assert_eq!(rx.pop(), Some(1));
+        assert_eq!(rx.pop(), Some(2));
+        assert!(rx.is_empty());
+    }
+
+    #[test]
+    pub fn push_fails_when_full() {
+        let mut ring: SpscRing<u32, 2> = SpscRing::new();
+        let (mut tx, mut rx) = ring.split();
+        // Go round the ring a few times to check that wrapping works
+        for i in 0..5 {
+            assert_eq!(tx.push(i), Ok(()));
+            assert_eq!(tx.push(i + 1), Ok(()));
+            assert!(tx.is_full());
+            assert_eq!(tx.push(i + 2), Err(i + 2));
+            assert_eq!(rx.pop(), Some(i));
+            assert_eq!(rx.pop(), Some(i + 1));
+        }
+    }
+
+    #[test]
+    pub fn batch_push_and_pop() {
+        let mut ring: SpscRing<u8, 8> = SpscRing::new();
+        let (mut tx, mut rx) = ring.split();
+        assert_eq!(tx.push_slice(&[1, 2, 3]), 3);
+        assert_eq!(tx.push_slice(&[4, 5, 6, 7, 8, 9, 10]), 5);
+        assert_eq!(tx.free(), 0);
+
+        let mut buf = [0; 5];
+        assert_eq!(rx.pop_slice(&mut buf), 5);
+        assert_eq!(buf, [1, 2, 3, 4, 5]);
+        assert_eq!(tx.push_slice(&[9, 10]), 2);
+        assert_eq!(rx.pop_slice(&mut buf), 5);
+        assert_eq!(buf, [6, 7, 8, 9, 10]);
+        assert_eq!(rx.pop_slice(&mut buf), 0);
+    }
+
+    #[test]
+    pub fn try_split_only_succeeds_once() {
+        static RING: SpscRing<u32, 4> = SpscRing::new();
+        assert!(RING.try_split().is_some());
+        assert!(RING.try_split().is_none());
+    }
+
+    #[test]
+    pub fn remaining_values_are_dropped() {
+        let value = Rc::new(());
+        {
+            let mut ring: SpscRing<Rc<()>, 4> = SpscRing::new();
+            let (mut tx, mut rx) = ring.split();
+            tx.push(value.clone()).unwrap();
+            tx.push(value.clone()).unwrap();
+            drop(rx.pop());
+            assert_eq!(Rc::strong_count(&value), 2);
+        }
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    pub fn threaded_stress() {
+        const COUNT: u64 = 200_000;
+        let mut ring: SpscRing<u64, 16> = SpscRing::new();
+        let (mut tx, mut rx) = ring.split();
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                let mut i = 0;
+                while i < COUNT {
+                    // Alternate between single and batch pushes
+                    let pushed = if i % 2 == 0 {
+                        tx.push(i).map(|_| 1).unwrap_or(0)
+                    } else {
+                        let batch = [i, i + 1, i + 2];
+                        let end = (COUNT - i).min(3) as usize;
+                        tx.push_slice(&batch[..end]) as u64
+                    };
+                    if pushed == 0 {
+                        // Let the consumer run if we're sharing a core
+                        thread::yield_now();
+                    }
+                    i += pushed;
+                }
+            });
+
+            let mut expected = 0;
+            let mut buf = [0; 5];
+            while expected < COUNT {
+                let n = rx.pop_slice(&mut buf);
+                for v in &buf[..n] {
+                    assert_eq!(*v, expected);
+                    expected += 1;
+                }
+                match rx.pop() {
+                    Some(v) => {
+                        assert_eq!(v, expected);
+                        expected += 1;
+                    }
+                    None => thread::yield_now(),
+                }
+            }
+            assert!(rx.is_empty());
+        });
+    }
+}
@@ -0,0 +1,196 @@
+//! A compact table of function symbols, which is embedded in the kernel image
+//! so that addresses can be shown as `function+offset`.
+//!
+//! The table is little endian, and laid out as:
+//!
+//! - the magic bytes `SYMS`
+//! - the number of symbols, as a u32
+//! - an entry for each symbol, sorted by address, holding the address (u64),
+//!   the size (u32) and the offset of its name in the names (u32)
+//! - the names, one after another. Each name ends where the next one starts.
+
+use core::convert::TryInto;
+use core::str;
+
+const MAGIC: &[u8; 4] = b"SYMS";
+const HEADER_SIZE: usize = 8;
+const ENTRY_SIZE: usize = 16;
+
+/// A function in the symbol table
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    pub address: u64,
+    /// The length of the function in bytes, which may be 0 if it's unknown
+    pub size: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The symbols need to be sorted by address
+    NotSorted,
+    /// The output buffer is too small, and needs to be this long
+    BufferTooSmall(usize),
+}
+
+/// A symbol table that has been read from its encoded form
+#[derive(Clone, Copy)]
+pub struct SymbolTable<'a> {
+    entries: &'a [u8],
+    names: &'a [u8],
+}
+
+impl<'a> SymbolTable<'a> {
+    /// Read an encoded table, returning None if it isn't a valid table
+    pub fn parse(bytes: &'a [u8]) -> Option<SymbolTable<'a>> {
+        if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC {
+            return None;
+        }
+        let count = read_u32(bytes, 4) as usize;
+        let names_start = count.checked_mul(ENTRY_SIZE)?.checked_add(HEADER_SIZE)?;
+        if names_start > bytes.len() {
+            return None;
+        }
+        Some(SymbolTable {
+            entries: &bytes[HEADER_SIZE..names_start],
+            names: &bytes[names_start..],
+        })
+    }
+
+    /// The number of symbols in the table
+    pub fn len(&self) -> usize {
+        self.entries.len() / ENTRY_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the symbol at an index, or None if there isn't one or its entry is
+    /// corrupt
+    pub fn get(&self, index: usize) -> Option<Symbol<'a>> {
+        let entry = self.entries.get(index * ENTRY_SIZE..(index + 1) * ENTRY_SIZE)?;
+        let start = read_u32(entry, 12) as usize;
+        let end = if index + 1 < self.len() {
+            read_u32(self.entries, (index + 1) * ENTRY_SIZE + 12) as usize
+        } else {
+            self.names.len()
+        };
+        let name = str::from_utf8(self.names.get(start..end)?).ok()?;
+        Some(Symbol {
+            name,
+            address: u64::from_le_bytes(entry[..8].try_into().unwrap()),
+            size: read_u32(entry, 8),
+        })
+    }
+
+    /// Find the function containing an address, and the offset of the address
+    /// from the start of it. Functions with unknown sizes are assumed to
+    /// extend up to the next symbol.
+    pub fn lookup(&self, address: u64) -> Option<(Symbol<'a>, u64)> {
+        // Find the last symbol that starts at or before the address
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.address(mid) <= address {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let symbol = self.get(low.checked_sub(1)?)?;
+        let offset = address - symbol.address;
+        if symbol.size != 0 && offset >= u64::from(symbol.size) {
+            return None;
+        }
+        Some((symbol, offset))
+    }
+
+    fn address(&self, index: usize) -> u64 {
+        let start = index * ENTRY_SIZE;
+        u64::from_le_bytes(self.entries[start..start + 8].try_into().unwrap())
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// The number of bytes needed to encode the symbols
+pub fn encoded_len(symbols: &[Symbol]) -> usize {
+    HEADER_SIZE + symbols.iter().map(|s| ENTRY_SIZE + s.name.len()).sum::<usize>()
+}
+
+/// Encode the symbols, which must be sorted by address, and return the number
+/// of bytes written
+pub fn encode(symbols: &[Symbol], out: &mut [u8]) -> Result<usize, EncodeError> {
+    if symbols.windows(2).any(|pair| pair[0].address > pair[1].address) {
+        return Err(EncodeError::NotSorted);
+    }
+    let len = encoded_len(symbols);
+    if out.len() < len {
+        return Err(EncodeError::BufferTooSmall(len));
+    }
+
+    out[..4].copy_from_slice(MAGIC);
+    out[4..8].copy_from_slice(&(symbols.len() as u32).to_le_bytes());
+    let mut name_offset = 0;
+    let names_start = HEADER_SIZE + symbols.len() * ENTRY_SIZE;
+    for (i, symbol) in symbols.iter().enumerate() {
+        let entry = &mut out[HEADER_SIZE + i * ENTRY_SIZE..HEADER_SIZE + (i + 1) * ENTRY_SIZE];
+        entry[..8].copy_from_slice(&symbol.address.to_le_bytes());
+        entry[8..12].copy_from_slice(&symbol.size.to_le_bytes());
+        entry[12..].copy_from_slice(&(name_offset as u32).to_le_bytes());
+
+        let start = names_start + name_offset;
+        out[start..start + symbol.name.len()].copy_from_slice(symbol.name.as_bytes());
+        name_offset += symbol.name.len();
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::vec;
+
+    const SYMBOLS: [Symbol; 3] = [
+        Symbol { name: "entry", address: 0x8_0000, size: 0x40 },
+        Symbol { name: "kernel::shell::run", address: 0x8_0100, size: 0 },
+        Symbol { name: "reset", address: 0x8_0200, size: 0x20 },
+    ];
+
+    #[test]
+    pub fn round_trip() {
+        let mut buf = vec![0; encoded_len(&SYMBOLS)];
+        assert_eq!(encode(&SYMBOLS, &mut buf), Ok(buf.len()));
+        let table = SymbolTable::parse(&buf).unwrap();
+        assert_eq!(table.len(), 3);
+        for (i, symbol) in SYMBOLS.iter().enumerate() {
+            assert_eq!(table.get(i), Some(*symbol));
+        }
+        assert_eq!(table.get(3), None);
+
+        assert_eq!(encode(&SYMBOLS, &mut buf[1..]), Err(EncodeError::BufferTooSmall(buf.len())));
+        let unsorted = [SYMBOLS[1], SYMBOLS[0]];
+        assert_eq!(encode(&unsorted, &mut buf), Err(EncodeError::NotSorted));
+        assert!(SymbolTable::parse(&buf[..4]).is_none());
+    }
+
+    #[test]
+    pub fn lookup() {
+        let mut buf = vec![0; encoded_len(&SYMBOLS)];
+        encode(&SYMBOLS, &mut buf).unwrap();
+        let table = SymbolTable::parse(&buf).unwrap();
+
+        assert_eq!(table.lookup(0x8_0000), Some((SYMBOLS[0], 0)));
+        assert_eq!(table.lookup(0x8_003C), Some((SYMBOLS[0], 0x3C)));
+        // Past the end of a function with a known size
+        assert_eq!(table.lookup(0x8_0040), None);
+        // A function without a size runs up to the next one
+        assert_eq!(table.lookup(0x8_01FF), Some((SYMBOLS[1], 0xFF)));
+        assert_eq!(table.lookup(0x8_0210), Some((SYMBOLS[2], 0x10)));
+        assert_eq!(table.lookup(0x7_FFFF), None);
+        assert_eq!(table.lookup(0x8_0220), None);
+    }
+}
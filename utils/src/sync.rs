@@ -1,39 +1,73 @@
 use core::cell::UnsafeCell;
-use core::hint::spin_loop;
 use core::ops::{Deref, DerefMut};
-use core::sync::atomic::{AtomicBool, Ordering, AtomicU64};
+use core::sync::atomic::Ordering;
+#[cfg(feature = "lock-debug")]
+use core::panic::Location;
+use self::primitives::{AtomicBool, spin_loop};
+
+#[macro_use]
+mod primitives;
+
+#[cfg(all(test, not(feature = "no-atomics")))]
+mod contention_tests;
+#[cfg(feature = "lock-debug")]
+pub mod debug;
+mod irq_safe;
+mod mcs;
+mod once;
+mod rwlock;
+mod ticket;
+
+pub use self::irq_safe::*;
+pub use self::mcs::*;
+pub use self::once::*;
+pub use self::rwlock::*;
+pub use self::ticket::*;
 
 /// A test-and-test-and-set lock
 pub struct Mutex<T> {
     lock: AtomicBool,
     data: UnsafeCell<T>,
+    #[cfg(feature = "lock-debug")]
+    debug: debug::LockDebug,
 }
 
 impl<T> Mutex<T> {
-    /// Construct a new mutex to protect a value
-    pub const fn new(v: T) -> Mutex<T> {
-        Mutex {
-            lock: AtomicBool::new(false),
-            data: UnsafeCell::new(v),
+    loom_const_fn! {
+        /// Construct a new mutex to protect a value
+        pub fn new(v: T) -> Mutex<T> {
+            Mutex {
+                lock: AtomicBool::new(false),
+                data: UnsafeCell::new(v),
+                #[cfg(feature = "lock-debug")]
+                debug: debug::LockDebug::new(),
+            }
         }
     }
 
     /// Lock the mutex and return a wrapper around the protected result
-    #[cfg(any(not(target_arch = "aarch64"), feature = "no-atomics"))]
+    #[cfg(any(not(target_arch = "aarch64"), feature = "no-atomics", feature = "lock-debug"))]
+    #[cfg_attr(feature = "lock-debug", track_caller)]
     pub fn lock(&self) -> LockedMutex<'_, T> {
+        #[cfg(feature = "lock-debug")]
+        let mut spins = {
+            self.debug.check_not_held_by_this_core(self.address(), Location::caller());
+            debug::SpinCounter::new()
+        };
+
         loop {
             if !self.lock.load(Ordering::Acquire) {
                 if self.try_set_lock_locked() {
-                    return LockedMutex {
-                        mutex: self
-                    }
+                    return self.guard();
                 }
             }
+            #[cfg(feature = "lock-debug")]
+            spins.spin(&self.debug, self.address(), Location::caller());
             spin_loop();
         }
     }
 
-    #[cfg(all(not(feature = "no-atomics"), target_arch = "aarch64"))]
+    #[cfg(all(not(feature = "no-atomics"), not(feature = "lock-debug"), target_arch = "aarch64"))]
     pub fn lock(&self) -> LockedMutex<'_, T> {
         let l: *const AtomicBool = &self.lock as *const AtomicBool;
         unsafe {
@@ -55,29 +89,57 @@ impl<T> Mutex<T> {
                 4:
                 " :: "r"(l), "r"(1) : "w1", "memory" : "volatile");
         }
-        LockedMutex {
-            mutex: self
-        }
+        self.guard()
     }
 
     /// If the mutex is unlocked, lock it and return a wrapper around the
     /// protected result; otherwise return None
+    #[cfg_attr(feature = "lock-debug", track_caller)]
     pub fn try_lock(&self) -> Option<LockedMutex<'_, T>> {
         if self.try_set_lock_locked() {
-            Some(LockedMutex {
-                mutex: self
-            })
+            Some(self.guard())
         } else {
             None
         }
     }
 
+    /// Try to lock the mutex, giving up and returning None if it is still
+    /// locked after `max_spins` attempts
+    #[cfg_attr(feature = "lock-debug", track_caller)]
+    pub fn lock_timeout(&self, max_spins: usize) -> Option<LockedMutex<'_, T>> {
+        for _ in 0..max_spins {
+            if !self.lock.load(Ordering::Acquire) && self.try_set_lock_locked() {
+                return Some(self.guard());
+            }
+            spin_loop();
+        }
+        None
+    }
+
+    /// Wrap the mutex in a guard, once the lock has been taken
+    #[cfg_attr(feature = "lock-debug", track_caller)]
+    fn guard(&self) -> LockedMutex<'_, T> {
+        #[cfg(feature = "lock-debug")]
+        self.debug.acquired(self.address(), Location::caller());
+        LockedMutex {
+            mutex: self
+        }
+    }
+
     /// Unlock the mutex. This method must only be called when dropping
     /// LockedMutex
     fn unlock(&self) {
+        #[cfg(feature = "lock-debug")]
+        self.debug.released(self.address());
         self.lock.store(false, Ordering::Release);
     }
 
+    /// The address of the mutex, which is used to identify it in debug output
+    #[cfg(feature = "lock-debug")]
+    fn address(&self) -> usize {
+        self as *const Mutex<T> as usize
+    }
+
     #[cfg(not(feature = "no-atomics"))]
     fn try_set_lock_locked(&self) -> bool {
         !self.lock.compare_and_swap(false, true, Ordering::Acquire)
@@ -96,6 +158,20 @@ impl<T> Mutex<T> {
 
 unsafe impl<T: Send> Sync for Mutex<T> { }
 
+/// Wake up any cores that are waiting in WFE for a lock to be released
+#[cfg(target_arch = "aarch64")]
+#[inline]
+fn send_event() {
+    unsafe {
+        asm!("SEV" :::: "volatile");
+    }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+#[inline]
+fn send_event() {
+}
+
 /// The result of locking a mutex
 pub struct LockedMutex<'a, T> {
     mutex: &'a Mutex<T>,
@@ -138,7 +214,7 @@ impl<'a, T> DerefMut for LockedMutex<'a, T> {
 }
 
 
-#[cfg(test)]
+#[cfg(all(test, not(loom)))]
 mod test {
     use super::*;
 
@@ -148,4 +224,13 @@ mod test {
         drop(m.lock());
         drop(m.lock());
     }
+
+    #[test]
+    pub fn lock_timeout_gives_up() {
+        let m = Mutex::new(());
+        let guard = m.lock();
+        assert!(m.lock_timeout(10).is_none());
+        drop(guard);
+        assert!(m.lock_timeout(10).is_some());
+    }
 }
@@ -0,0 +1,214 @@
+//! Contention tests for the locks, which check that no two threads are ever
+//! inside a critical section at the same time.
+//!
+//! Normally these run as stress tests using host threads. When built with
+//! `--cfg loom`, the same tests are run under loom instead, which explores
+//! every interleaving of the atomic operations (with smaller thread and
+//! iteration counts to keep that tractable).
+//!
+//! The `no-atomics` fallbacks are only valid while a single core is running,
+//! so these tests are skipped when that feature is enabled. The aarch64
+//! exclusive-access paths can only be exercised on the hardware.
+
+use super::primitives::{AtomicBool, spin_loop};
+use super::*;
+use core::sync::atomic::Ordering;
+
+#[cfg(loom)]
+use loom::{cell::UnsafeCell, sync::Arc, sync::atomic::AtomicUsize, thread};
+#[cfg(not(loom))]
+use std::{sync::Arc, sync::atomic::AtomicUsize, thread};
+
+/// A stand-in for loom's `UnsafeCell`, which lets loom check that every access
+/// to the counter is ordered by the lock
+#[cfg(not(loom))]
+struct UnsafeCell<T>(core::cell::UnsafeCell<T>);
+
+#[cfg(not(loom))]
+impl<T> UnsafeCell<T> {
+    fn new(v: T) -> UnsafeCell<T> {
+        UnsafeCell(core::cell::UnsafeCell::new(v))
+    }
+
+    fn with_mut<R, F: FnOnce(*mut T) -> R>(&self, f: F) -> R {
+        f(self.0.get())
+    }
+}
+
+#[cfg(loom)]
+const THREADS: usize = 2;
+#[cfg(loom)]
+const ITERATIONS: usize = 2;
+
+#[cfg(not(loom))]
+const THREADS: usize = 4;
+#[cfg(not(loom))]
+const ITERATIONS: usize = 5_000;
+
+/// Explore the interleavings of `f`. Bounding the number of preemptions
+/// keeps the spinning locks tractable, and most bugs need very few of them.
+#[cfg(loom)]
+fn model<F: Fn() + Sync + Send + 'static>(f: F) {
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+    builder.check(f);
+}
+
+#[cfg(not(loom))]
+fn model<F: Fn() + Sync + Send + 'static>(f: F) {
+    f();
+}
+
+/// State that is only ever touched inside a critical section. Entering checks
+/// that nobody else is inside, and the counter is updated non-atomically so
+/// that missing exclusion would show up as lost updates.
+struct Critical {
+    inside: AtomicBool,
+    count: UnsafeCell<usize>,
+}
+
+unsafe impl Sync for Critical { }
+
+impl Critical {
+    fn new() -> Critical {
+        Critical {
+            inside: AtomicBool::new(false),
+            count: UnsafeCell::new(0),
+        }
+    }
+
+    /// Run a critical section. Must only be called while holding the lock.
+    fn enter(&self) {
+        assert!(!self.inside.swap(true, Ordering::Relaxed), "Two threads held the lock at once");
+        let count = self.count.with_mut(|c| unsafe { *c });
+        // Give other threads the chance to break in part way through
+        spin_loop();
+        self.count.with_mut(|c| unsafe { *c = count + 1 });
+        self.inside.store(false, Ordering::Relaxed);
+    }
+
+    fn count(&self) -> usize {
+        self.count.with_mut(|c| unsafe { *c })
+    }
+}
+
+/// Run `iteration` on `THREADS` threads, `ITERATIONS` times each, then check
+/// that every critical section ran exactly once
+fn check_exclusion<L, F>(new_lock: fn() -> L, iteration: F)
+    where L: Send + Sync + 'static, F: Fn(&L, &Critical) + Send + Sync + Copy + 'static
+{
+    model(move || {
+        let shared = Arc::new((new_lock(), Critical::new()));
+        let threads: std::vec::Vec<_> = (0..THREADS).map(|_| {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                for _ in 0..ITERATIONS {
+                    iteration(&shared.0, &shared.1);
+                }
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(shared.1.count(), THREADS * ITERATIONS);
+    });
+}
+
+#[test]
+fn mutex_lock() {
+    check_exclusion(|| Mutex::new(()), |m, c| {
+        let _guard = m.lock();
+        c.enter();
+    });
+}
+
+#[test]
+fn mutex_try_lock() {
+    check_exclusion(|| Mutex::new(()), |m, c| {
+        loop {
+            if let Some(_guard) = m.try_lock() {
+                c.enter();
+                return;
+            }
+            spin_loop();
+        }
+    });
+}
+
+#[test]
+fn irq_safe_mutex_lock() {
+    check_exclusion(|| IrqSafeMutex::new(()), |m, c| {
+        let _guard = m.lock();
+        c.enter();
+    });
+}
+
+#[test]
+fn ticket_lock() {
+    check_exclusion(|| TicketLock::new(()), |m, c| {
+        let _guard = m.lock();
+        c.enter();
+    });
+}
+
+#[test]
+fn ticket_try_lock() {
+    check_exclusion(|| TicketLock::new(()), |m, c| {
+        loop {
+            if let Some(_guard) = m.try_lock() {
+                c.enter();
+                return;
+            }
+            spin_loop();
+        }
+    });
+}
+
+#[test]
+fn mcs_lock() {
+    check_exclusion(|| McsLock::new(()), |m, c| {
+        let mut node = McsNode::new();
+        let _guard = unsafe { m.lock(&mut node) };
+        c.enter();
+    });
+}
+
+#[test]
+fn rwlock_write() {
+    check_exclusion(|| RwLock::new(()), |m, c| {
+        let _guard = m.write();
+        c.enter();
+    });
+}
+
+#[test]
+fn rwlock_readers_exclude_writer() {
+    // Alternate between reading and writing. Readers check that no writer is
+    // inside, but don't mark themselves as inside as they can overlap.
+    check_exclusion(|| RwLock::new(()), |m, c| {
+        {
+            let _guard = m.read();
+            assert!(!c.inside.load(Ordering::Relaxed), "A reader overlapped with a writer");
+        }
+        let _guard = m.write();
+        c.enter();
+    });
+}
+
+#[test]
+fn once_runs_once() {
+    model(|| {
+        let shared = Arc::new((Once::new(), AtomicUsize::new(0)));
+        let threads: std::vec::Vec<_> = (0..THREADS).map(|_| {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                shared.0.call_once(|| { shared.1.fetch_add(1, Ordering::Relaxed); });
+                // Everyone must see the effects of the initialiser
+                assert_eq!(shared.1.load(Ordering::Relaxed), 1);
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+    });
+}
@@ -0,0 +1,274 @@
+//! Lock debugging, enabled by the `lock-debug` feature.
+//!
+//! Each `Mutex` records which core holds it and where it was locked from, and
+//! every held lock is listed in a global table so that the panic handler can
+//! report them. Waiting for a lock for too long triggers a warning through a
+//! handler that the kernel installs, and trying to take a lock that the
+//! current core already holds panics rather than spinning forever.
+
+use crate::cpu;
+use core::panic::Location;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// The maximum number of locks that can be tracked at once. Locks taken when
+/// the table is full still work, but won't be listed by `held_locks`.
+const MAX_TRACKED_LOCKS: usize = 32;
+
+/// The default number of spins before `lock` starts complaining
+const DEFAULT_SPIN_WARNING_THRESHOLD: usize = 10_000_000;
+
+/// Details of a lock that is currently held
+#[derive(Clone, Copy, Debug)]
+pub struct HeldLock {
+    /// The address of the lock
+    pub address: usize,
+    /// The core that locked it
+    pub core: usize,
+    /// Where it was locked from
+    pub location: &'static Location<'static>,
+}
+
+/// Details passed to the spin warning handler when a core has been waiting
+/// for a lock for a long time
+#[derive(Clone, Copy, Debug)]
+pub struct SlowLock {
+    /// The address of the lock
+    pub address: usize,
+    /// Where the waiting core is trying to lock it from
+    pub waiter: &'static Location<'static>,
+    /// The number of times that the waiter has spun so far
+    pub spins: usize,
+    /// The current holder of the lock, if it is known
+    pub holder: Option<HeldLock>,
+}
+
+static SPIN_WARNING_THRESHOLD: AtomicUsize = AtomicUsize::new(DEFAULT_SPIN_WARNING_THRESHOLD);
+static SPIN_WARNING_HANDLER: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Set the number of spins after which a waiting core reports the lock as
+/// slow. The handler is called again each time that many more spins pass.
+pub fn set_spin_warning_threshold(spins: usize) {
+    SPIN_WARNING_THRESHOLD.store(spins, Ordering::Relaxed);
+}
+
+/// Set the function that is called when a core has been spinning on a lock
+/// for longer than the threshold.
+///
+/// The handler is called while the lock is still being waited for, so it must
+/// not take any lock that could be the one being waited on.
+pub fn set_spin_warning_handler(handler: fn(&SlowLock)) {
+    SPIN_WARNING_HANDLER.store(handler as *mut (), Ordering::Release);
+}
+
+/// An entry in the table of held locks. An address of zero means that the
+/// slot is free.
+struct Slot {
+    address: AtomicUsize,
+    core: AtomicUsize,
+    location: AtomicPtr<Location<'static>>,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: Slot = Slot {
+    address: AtomicUsize::new(0),
+    core: AtomicUsize::new(0),
+    location: AtomicPtr::new(ptr::null_mut()),
+};
+
+static HELD_LOCKS: [Slot; MAX_TRACKED_LOCKS] = [EMPTY_SLOT; MAX_TRACKED_LOCKS];
+
+/// Get an iterator over the locks which are currently held.
+///
+/// This is intended to be used from the panic handler, so it doesn't take any
+/// locks itself. Entries may be missed or stale if other cores are locking
+/// and unlocking at the same time.
+pub fn held_locks() -> impl Iterator<Item = HeldLock> {
+    HELD_LOCKS.iter().filter_map(read_slot)
+}
+
+fn read_slot(slot: &Slot) -> Option<HeldLock> {
+    let address = slot.address.load(Ordering::Acquire);
+    let location = slot.location.load(Ordering::Relaxed);
+    if address == 0 || location.is_null() {
+        return None;
+    }
+    Some(HeldLock {
+        address,
+        core: slot.core.load(Ordering::Relaxed),
+        location: unsafe { &*location },
+    })
+}
+
+#[cfg(not(feature = "no-atomics"))]
+fn claim_slot(slot: &Slot, address: usize) -> bool {
+    slot.address.compare_exchange(0, address, Ordering::Acquire, Ordering::Relaxed).is_ok()
+}
+
+#[cfg(feature = "no-atomics")]
+fn claim_slot(slot: &Slot, address: usize) -> bool {
+    if slot.address.load(Ordering::Acquire) == 0 {
+        slot.address.store(address, Ordering::Relaxed);
+        true
+    } else {
+        false
+    }
+}
+
+/// The debug state stored in each `Mutex`
+pub(super) struct LockDebug {
+    /// The core that holds the lock, plus one, or zero if it's not held
+    owner: AtomicUsize,
+    /// Where the lock was taken from
+    location: AtomicPtr<Location<'static>>,
+}
+
+impl LockDebug {
+    pub(super) const fn new() -> LockDebug {
+        LockDebug {
+            owner: AtomicUsize::new(0),
+            location: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Get the current holder of the lock, if it's held
+    fn holder(&self, address: usize) -> Option<HeldLock> {
+        let owner = self.owner.load(Ordering::Relaxed);
+        let location = self.location.load(Ordering::Relaxed);
+        if owner == 0 || location.is_null() {
+            None
+        } else {
+            Some(HeldLock {
+                address,
+                core: owner - 1,
+                location: unsafe { &*location },
+            })
+        }
+    }
+
+    /// Panic if this core already holds the lock, because waiting for it
+    /// would never finish
+    pub(super) fn check_not_held_by_this_core(&self, address: usize, waiter: &'static Location<'static>) {
+        if let Some(holder) = self.holder(address) {
+            if holder.core == cpu::core_id() {
+                panic!("Recursive lock of {:#x} at {}: core {} already locked it at {}",
+                    address, waiter, holder.core, holder.location);
+            }
+        }
+    }
+
+    /// Record that the lock has just been taken by this core
+    pub(super) fn acquired(&self, address: usize, location: &'static Location<'static>) {
+        let core = cpu::core_id();
+        self.location.store(location as *const Location as *mut Location, Ordering::Relaxed);
+        self.owner.store(core + 1, Ordering::Relaxed);
+
+        for slot in HELD_LOCKS.iter() {
+            if claim_slot(slot, address) {
+                slot.core.store(core, Ordering::Relaxed);
+                slot.location.store(location as *const Location as *mut Location, Ordering::Release);
+                return;
+            }
+        }
+    }
+
+    /// Record that the lock is about to be released
+    pub(super) fn released(&self, address: usize) {
+        self.owner.store(0, Ordering::Relaxed);
+        self.location.store(ptr::null_mut(), Ordering::Relaxed);
+
+        for slot in HELD_LOCKS.iter() {
+            if slot.address.load(Ordering::Relaxed) == address {
+                slot.location.store(ptr::null_mut(), Ordering::Relaxed);
+                slot.address.store(0, Ordering::Release);
+                return;
+            }
+        }
+    }
+}
+
+/// Counts the spins while waiting for a lock, and reports the lock as slow
+/// whenever the threshold is passed
+pub(super) struct SpinCounter {
+    spins: usize,
+    since_warning: usize,
+}
+
+impl SpinCounter {
+    pub(super) fn new() -> SpinCounter {
+        SpinCounter {
+            spins: 0,
+            since_warning: 0,
+        }
+    }
+
+    /// Record another spin on the lock at `address`
+    pub(super) fn spin(&mut self, debug: &LockDebug, address: usize, waiter: &'static Location<'static>) {
+        self.spins += 1;
+        self.since_warning += 1;
+        let threshold = SPIN_WARNING_THRESHOLD.load(Ordering::Relaxed);
+        if threshold == 0 || self.since_warning < threshold {
+            return;
+        }
+        self.since_warning = 0;
+
+        let handler = SPIN_WARNING_HANDLER.load(Ordering::Acquire);
+        if !handler.is_null() {
+            let handler: fn(&SlowLock) = unsafe { core::mem::transmute(handler) };
+            handler(&SlowLock {
+                address,
+                waiter,
+                spins: self.spins,
+                holder: debug.holder(address),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sync::Mutex;
+    use std::thread;
+
+    #[test]
+    pub fn held_locks_are_listed() {
+        let m = Mutex::new(());
+        let address = &m as *const Mutex<()> as usize;
+        let guard = m.lock();
+        let held = held_locks().find(|l| l.address == address).unwrap();
+        assert_eq!(held.core, cpu::core_id());
+        assert_eq!(held.location.file(), file!());
+        drop(guard);
+        assert!(held_locks().all(|l| l.address != address));
+    }
+
+    #[test]
+    #[should_panic(expected = "Recursive lock")]
+    pub fn recursive_lock_panics() {
+        let m = Mutex::new(());
+        let _guard = m.lock();
+        let _again = m.lock();
+    }
+
+    #[test]
+    pub fn slow_locks_are_reported() {
+        static REPORTED: AtomicUsize = AtomicUsize::new(0);
+        fn handler(slow: &SlowLock) {
+            REPORTED.store(slow.holder.unwrap().address, Ordering::SeqCst);
+        }
+
+        static M: Mutex<()> = Mutex::new(());
+        let address = &M as *const Mutex<()> as usize;
+        set_spin_warning_threshold(100);
+        set_spin_warning_handler(handler);
+
+        let guard = M.lock();
+        let waiter = thread::spawn(|| drop(M.lock()));
+        while REPORTED.load(Ordering::SeqCst) != address {
+            thread::yield_now();
+        }
+        drop(guard);
+        waiter.join().unwrap();
+    }
+}
@@ -0,0 +1,246 @@
+use super::{Mutex, LockedMutex, RwLock, ReadLockedRwLock, WriteLockedRwLock};
+use crate::irq::{self, SavedDaif};
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+
+/// A mutex which masks IRQs on the current core while it is held, so that it
+/// can be shared between normal code and interrupt handlers.
+///
+/// If a plain `Mutex` is held when an interrupt arrives and the handler tries
+/// to take the same lock, the handler will spin forever because the code that
+/// would release the lock can't run until the handler returns. Masking IRQs
+/// for the duration of the critical section prevents that from happening.
+pub struct IrqSafeMutex<T> {
+    mutex: Mutex<T>,
+}
+
+impl<T> IrqSafeMutex<T> {
+    loom_const_fn! {
+        /// Construct a new mutex to protect a value
+        pub fn new(v: T) -> IrqSafeMutex<T> {
+            IrqSafeMutex {
+                mutex: Mutex::new(v),
+            }
+        }
+    }
+
+    /// Mask IRQs, then lock the mutex and return a wrapper around the
+    /// protected result. IRQs are restored to their previous state when the
+    /// wrapper is dropped.
+    #[cfg_attr(feature = "lock-debug", track_caller)]
+    pub fn lock(&self) -> LockedIrqSafeMutex<'_, T> {
+        // IRQs need to be masked before taking the lock, otherwise an
+        // interrupt could arrive between acquiring it and masking them
+        let daif = irq::save_and_disable();
+        LockedIrqSafeMutex {
+            guard: ManuallyDrop::new(self.mutex.lock()),
+            daif,
+        }
+    }
+
+    /// If the mutex is unlocked, mask IRQs, lock it and return a wrapper
+    /// around the protected result; otherwise return None and leave the
+    /// interrupt state unchanged
+    #[cfg_attr(feature = "lock-debug", track_caller)]
+    pub fn try_lock(&self) -> Option<LockedIrqSafeMutex<'_, T>> {
+        let daif = irq::save_and_disable();
+        match self.mutex.try_lock() {
+            Some(guard) => Some(LockedIrqSafeMutex {
+                guard: ManuallyDrop::new(guard),
+                daif,
+            }),
+            None => {
+                irq::restore(daif);
+                None
+            }
+        }
+    }
+}
+
+/// The result of locking an `IrqSafeMutex`
+pub struct LockedIrqSafeMutex<'a, T> {
+    guard: ManuallyDrop<LockedMutex<'a, T>>,
+    daif: SavedDaif,
+}
+
+impl<'a, T> LockedIrqSafeMutex<'a, T> {
+    /// Get a reference to the value protected by this mutex
+    pub fn value(&self) -> &T {
+        self.guard.value()
+    }
+
+    /// Get a mutable reference to the value protected by this mutex
+    pub fn value_mut(&mut self) -> &mut T {
+        self.guard.value_mut()
+    }
+}
+
+impl<'a, T> Drop for LockedIrqSafeMutex<'a, T> {
+    fn drop(&mut self) {
+        // Release the lock before unmasking IRQs, so that a pending interrupt
+        // that wants the lock can take it straight away
+        unsafe {
+            ManuallyDrop::drop(&mut self.guard);
+        }
+        irq::restore(self.daif);
+    }
+}
+
+impl<'a, T> Deref for LockedIrqSafeMutex<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value()
+    }
+}
+
+impl<'a, T> DerefMut for LockedIrqSafeMutex<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value_mut()
+    }
+}
+
+/// A reader-writer lock which masks IRQs on the current core while it is held,
+/// for the same reasons as `IrqSafeMutex`
+pub struct IrqSafeRwLock<T> {
+    lock: RwLock<T>,
+}
+
+impl<T> IrqSafeRwLock<T> {
+    loom_const_fn! {
+        /// Construct a new lock to protect a value
+        pub fn new(v: T) -> IrqSafeRwLock<T> {
+            IrqSafeRwLock {
+                lock: RwLock::new(v),
+            }
+        }
+    }
+
+    /// Mask IRQs, then lock the value for reading. IRQs are restored when the
+    /// wrapper is dropped.
+    pub fn read(&self) -> ReadLockedIrqSafeRwLock<'_, T> {
+        let daif = irq::save_and_disable();
+        ReadLockedIrqSafeRwLock {
+            guard: ManuallyDrop::new(self.lock.read()),
+            daif,
+        }
+    }
+
+    /// If the value can be locked for reading straight away, mask IRQs and do
+    /// so; otherwise return None and leave the interrupt state unchanged
+    pub fn try_read(&self) -> Option<ReadLockedIrqSafeRwLock<'_, T>> {
+        let daif = irq::save_and_disable();
+        match self.lock.try_read() {
+            Some(guard) => Some(ReadLockedIrqSafeRwLock {
+                guard: ManuallyDrop::new(guard),
+                daif,
+            }),
+            None => {
+                irq::restore(daif);
+                None
+            }
+        }
+    }
+
+    /// Mask IRQs, then lock the value for writing. IRQs are restored when the
+    /// wrapper is dropped.
+    pub fn write(&self) -> WriteLockedIrqSafeRwLock<'_, T> {
+        let daif = irq::save_and_disable();
+        WriteLockedIrqSafeRwLock {
+            guard: ManuallyDrop::new(self.lock.write()),
+            daif,
+        }
+    }
+
+    /// Get the protected value without taking the lock (see
+    /// `RwLock::read_unchecked`)
+    ///
+    /// # Safety
+    ///
+    /// The value may be modified at the same time by a writer, so it must not
+    /// be relied upon unless every other core has been stopped.
+    pub unsafe fn read_unchecked(&self) -> &T {
+        self.lock.read_unchecked()
+    }
+}
+
+/// The result of locking an `IrqSafeRwLock` for reading
+pub struct ReadLockedIrqSafeRwLock<'a, T> {
+    guard: ManuallyDrop<ReadLockedRwLock<'a, T>>,
+    daif: SavedDaif,
+}
+
+impl<'a, T> Drop for ReadLockedIrqSafeRwLock<'a, T> {
+    fn drop(&mut self) {
+        unsafe {
+            ManuallyDrop::drop(&mut self.guard);
+        }
+        irq::restore(self.daif);
+    }
+}
+
+impl<'a, T> Deref for ReadLockedIrqSafeRwLock<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.value()
+    }
+}
+
+/// The result of locking an `IrqSafeRwLock` for writing
+pub struct WriteLockedIrqSafeRwLock<'a, T> {
+    guard: ManuallyDrop<WriteLockedRwLock<'a, T>>,
+    daif: SavedDaif,
+}
+
+impl<'a, T> Drop for WriteLockedIrqSafeRwLock<'a, T> {
+    fn drop(&mut self) {
+        unsafe {
+            ManuallyDrop::drop(&mut self.guard);
+        }
+        irq::restore(self.daif);
+    }
+}
+
+impl<'a, T> Deref for WriteLockedIrqSafeRwLock<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.value()
+    }
+}
+
+impl<'a, T> DerefMut for WriteLockedIrqSafeRwLock<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.value_mut()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn lock_is_released_on_drop() {
+        let m = IrqSafeMutex::new(1);
+        {
+            let mut guard = m.lock();
+            *guard += 1;
+            assert!(m.try_lock().is_none());
+        }
+        assert_eq!(*m.try_lock().unwrap(), 2);
+    }
+
+    #[test]
+    pub fn rwlock_is_released_on_drop() {
+        let l = IrqSafeRwLock::new(1);
+        {
+            let mut guard = l.write();
+            *guard += 1;
+            assert!(l.try_read().is_none());
+        }
+        let a = l.read();
+        let b = l.try_read().unwrap();
+        assert_eq!(*a + *b, 4);
+    }
+}
@@ -0,0 +1,282 @@
+use super::send_event;
+#[cfg(any(not(target_arch = "aarch64"), feature = "no-atomics"))]
+use super::primitives::spin_loop;
+use super::primitives::{AtomicBool, AtomicPtr};
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::sync::atomic::Ordering;
+
+/// An entry in the queue of cores waiting for an `McsLock`.
+///
+/// Each core spins on the `locked` flag in its own node rather than on the
+/// lock itself, so waiting cores don't fight over the same cache line. The
+/// node must stay in place for as long as the lock is held, so it is borrowed
+/// by the guard, and the guard must be dropped to unlink it again (see
+/// `McsLock::lock`).
+pub struct McsNode {
+    next: AtomicPtr<McsNode>,
+    locked: AtomicBool,
+}
+
+impl McsNode {
+    loom_const_fn! {
+        /// Construct a new queue node, ready to be passed to `McsLock::lock`
+        pub fn new() -> McsNode {
+            McsNode {
+                next: AtomicPtr::new(ptr::null_mut()),
+                locked: AtomicBool::new(false),
+            }
+        }
+    }
+}
+
+impl Default for McsNode {
+    fn default() -> McsNode {
+        McsNode::new()
+    }
+}
+
+/// A fair queued spinlock (Mellor-Crummey and Scott).
+///
+/// The lock holds a pointer to the last node in the queue of waiters. A core
+/// that wants the lock appends its node to the queue, then spins on its own
+/// node until its predecessor hands the lock over.
+pub struct McsLock<T> {
+    tail: AtomicPtr<McsNode>,
+    data: UnsafeCell<T>,
+}
+
+impl<T> McsLock<T> {
+    loom_const_fn! {
+        /// Construct a new MCS lock to protect a value
+        pub fn new(v: T) -> McsLock<T> {
+            McsLock {
+                tail: AtomicPtr::new(ptr::null_mut()),
+                data: UnsafeCell::new(v),
+            }
+        }
+    }
+
+    /// Join the queue using `node`, then wait until the lock is handed to us
+    /// and return a wrapper around the protected result
+    ///
+    /// # Safety
+    ///
+    /// The guard must be dropped, not leaked with `mem::forget` or similar.
+    /// Otherwise `node` stays linked into the queue after its borrow ends, and
+    /// the next core to lock will write to it wherever it used to be.
+    pub unsafe fn lock<'a>(&'a self, node: &'a mut McsNode) -> LockedMcsLock<'a, T> {
+        node.next.store(ptr::null_mut(), Ordering::Relaxed);
+        node.locked.store(true, Ordering::Relaxed);
+
+        let prev = self.swap_tail(node);
+        if !prev.is_null() {
+            // Someone else holds the lock, so link ourselves in behind them
+            // and wait for them to hand it over
+            (*prev).next.store(node, Ordering::Release);
+            wait_for_handover(&node.locked);
+        }
+
+        LockedMcsLock {
+            lock: self,
+            node,
+        }
+    }
+
+    /// If nobody holds or is waiting for the lock, lock it using `node` and
+    /// return a wrapper around the protected result; otherwise return None
+    ///
+    /// # Safety
+    ///
+    /// As for `lock`, the guard must be dropped rather than leaked.
+    pub unsafe fn try_lock<'a>(&'a self, node: &'a mut McsNode) -> Option<LockedMcsLock<'a, T>> {
+        node.next.store(ptr::null_mut(), Ordering::Relaxed);
+        node.locked.store(false, Ordering::Relaxed);
+
+        if self.compare_and_set_tail(ptr::null_mut(), node, Ordering::Acquire) {
+            Some(LockedMcsLock {
+                lock: self,
+                node,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Unlock the mutex, handing it to the next node in the queue if there is
+    /// one. This method must only be called when dropping LockedMcsLock
+    fn unlock(&self, node: &McsNode) {
+        let mut next = node.next.load(Ordering::Acquire);
+        if next.is_null() {
+            // There's nobody visibly waiting, so try to mark the lock as free
+            let node_ptr = node as *const McsNode as *mut McsNode;
+            if self.compare_and_set_tail(node_ptr, ptr::null_mut(), Ordering::Release) {
+                return;
+            }
+
+            // Someone has swapped the tail but hasn't linked themselves to us
+            // yet, so wait until they have
+            loop {
+                next = node.next.load(Ordering::Acquire);
+                if !next.is_null() {
+                    break;
+                }
+                spin_wait();
+            }
+        }
+
+        unsafe {
+            (*next).locked.store(false, Ordering::Release);
+        }
+        send_event();
+    }
+
+    #[cfg(all(not(feature = "no-atomics"), not(target_arch = "aarch64")))]
+    fn swap_tail(&self, node: *mut McsNode) -> *mut McsNode {
+        self.tail.swap(node, Ordering::AcqRel)
+    }
+
+    #[cfg(all(not(feature = "no-atomics"), target_arch = "aarch64"))]
+    fn swap_tail(&self, node: *mut McsNode) -> *mut McsNode {
+        let tail: *const AtomicPtr<McsNode> = &self.tail as *const AtomicPtr<McsNode>;
+        let prev: *mut McsNode;
+        unsafe {
+            asm!("1:
+                    LDAXR   $0, [$1]
+                    STLXR   w3, $2, [$1]
+                    CBNZ    w3, 1b
+                " : "=&r"(prev) : "r"(tail), "r"(node) : "w3", "memory" : "volatile");
+        }
+        prev
+    }
+
+    #[cfg(feature = "no-atomics")]
+    fn swap_tail(&self, node: *mut McsNode) -> *mut McsNode {
+        let prev = self.tail.load(Ordering::Acquire);
+        self.tail.store(node, Ordering::Release);
+        prev
+    }
+
+    #[cfg(not(feature = "no-atomics"))]
+    fn compare_and_set_tail(&self, current: *mut McsNode, new: *mut McsNode, order: Ordering) -> bool {
+        self.tail.compare_exchange(current, new, order, Ordering::Relaxed).is_ok()
+    }
+
+    #[cfg(feature = "no-atomics")]
+    fn compare_and_set_tail(&self, current: *mut McsNode, new: *mut McsNode, _order: Ordering) -> bool {
+        if self.tail.load(Ordering::Acquire) == current {
+            self.tail.store(new, Ordering::Release);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+unsafe impl<T: Send> Sync for McsLock<T> { }
+
+/// Wait until our predecessor in the queue clears our `locked` flag
+#[cfg(any(not(target_arch = "aarch64"), feature = "no-atomics"))]
+fn wait_for_handover(locked: &AtomicBool) {
+    while locked.load(Ordering::Acquire) {
+        spin_loop();
+    }
+}
+
+#[cfg(all(not(feature = "no-atomics"), target_arch = "aarch64"))]
+fn wait_for_handover(locked: &AtomicBool) {
+    let l: *const AtomicBool = locked as *const AtomicBool;
+    unsafe {
+        asm!("    SEVL
+                1:
+                    WFE
+                    // The exclusive load means that the handover store will
+                    // generate a wakeup event
+                    LDAXRB  w1, [$0]
+                    CBNZ    w1, 1b
+                " :: "r"(l) : "w1", "memory" : "volatile");
+    }
+}
+
+#[cfg(any(not(target_arch = "aarch64"), feature = "no-atomics"))]
+fn spin_wait() {
+    spin_loop();
+}
+
+#[cfg(all(not(feature = "no-atomics"), target_arch = "aarch64"))]
+fn spin_wait() {
+    unsafe {
+        asm!("YIELD" :::: "volatile");
+    }
+}
+
+/// The result of locking an MCS lock
+pub struct LockedMcsLock<'a, T> {
+    lock: &'a McsLock<T>,
+    node: &'a McsNode,
+}
+
+impl<'a, T> LockedMcsLock<'a, T> {
+    /// Get a reference to the value protected by this lock
+    pub fn value(&self) -> &T {
+        unsafe {
+            &*self.lock.data.get()
+        }
+    }
+
+    /// Get a mutable reference to the value protected by this lock
+    pub fn value_mut(&mut self) -> &mut T {
+        unsafe {
+            &mut *self.lock.data.get()
+        }
+    }
+}
+
+impl<'a, T> Drop for LockedMcsLock<'a, T> {
+    fn drop(&mut self) {
+        self.lock.unlock(self.node);
+    }
+}
+
+impl<'a, T> Deref for LockedMcsLock<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value()
+    }
+}
+
+impl<'a, T> DerefMut for LockedMcsLock<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value_mut()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn repeated_acquire() {
+        let m = McsLock::new(());
+        let mut node = McsNode::new();
+        unsafe {
+            drop(m.lock(&mut node));
+            drop(m.lock(&mut node));
+        }
+    }
+
+    #[test]
+    pub fn try_lock_fails_while_held() {
+        let m = McsLock::new(());
+        let mut node_a = McsNode::new();
+        let mut node_b = McsNode::new();
+        unsafe {
+            let guard = m.lock(&mut node_a);
+            assert!(m.try_lock(&mut node_b).is_none());
+            drop(guard);
+            assert!(m.try_lock(&mut node_b).is_some());
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use super::primitives::{AtomicU8, spin_loop};
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use core::sync::atomic::Ordering;
+
+const INCOMPLETE: u8 = 0;
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+
+/// A primitive which runs a one-time initialisation exactly once, even if
+/// several cores race to do it
+pub struct Once {
+    state: AtomicU8,
+}
+
+impl Once {
+    loom_const_fn! {
+        /// Construct a new `Once` which hasn't been run yet
+        pub fn new() -> Once {
+            Once {
+                state: AtomicU8::new(INCOMPLETE),
+            }
+        }
+    }
+
+    /// Run `f` if no call to `call_once` has run yet. If another core is
+    /// currently running its initialiser, wait for it to finish instead.
+    ///
+    /// When this returns, the initialisation is guaranteed to have completed
+    /// and its effects are visible to this core.
+    pub fn call_once<F: FnOnce()>(&self, f: F) {
+        if self.is_completed() {
+            return;
+        }
+
+        if self.try_start() {
+            f();
+            self.state.store(COMPLETE, Ordering::Release);
+        } else {
+            self.wait();
+        }
+    }
+
+    /// Whether the initialisation has completed
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+
+    /// Wait for the core that is running the initialiser to finish
+    fn wait(&self) {
+        while !self.is_completed() {
+            spin_loop();
+        }
+    }
+
+    #[cfg(not(feature = "no-atomics"))]
+    fn try_start(&self) -> bool {
+        self.state.compare_exchange(INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+
+    #[cfg(feature = "no-atomics")]
+    fn try_start(&self) -> bool {
+        if self.state.load(Ordering::Acquire) == INCOMPLETE {
+            self.state.store(RUNNING, Ordering::Relaxed);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Default for Once {
+    fn default() -> Once {
+        Once::new()
+    }
+}
+
+/// A cell which can be written to once, and then read from without locking
+pub struct OnceCell<T> {
+    once: Once,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> OnceCell<T> {
+    loom_const_fn! {
+        /// Construct a new, empty cell
+        pub fn new() -> OnceCell<T> {
+            OnceCell {
+                once: Once::new(),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            }
+        }
+    }
+
+    /// Get a reference to the value, or None if it hasn't been set yet
+    pub fn get(&self) -> Option<&T> {
+        if self.once.is_completed() {
+            Some(unsafe { self.get_unchecked() })
+        } else {
+            None
+        }
+    }
+
+    /// Set the value of the cell. If it has already been set, the cell is
+    /// left unchanged and `v` is returned.
+    pub fn set(&self, v: T) -> Result<(), T> {
+        let mut v = Some(v);
+        self.once.call_once(|| unsafe {
+            (*self.value.get()).as_mut_ptr().write(v.take().unwrap());
+        });
+        match v {
+            None => Ok(()),
+            Some(v) => Err(v),
+        }
+    }
+
+    /// Get a reference to the value, initialising it with `f` if it hasn't
+    /// been set yet
+    pub fn get_or_init<F: FnOnce() -> T>(&self, f: F) -> &T {
+        self.once.call_once(|| unsafe {
+            (*self.value.get()).as_mut_ptr().write(f());
+        });
+        unsafe { self.get_unchecked() }
+    }
+
+    /// Get a reference to the value, which must have been initialised
+    unsafe fn get_unchecked(&self) -> &T {
+        &*(*self.value.get()).as_ptr()
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> OnceCell<T> {
+        OnceCell::new()
+    }
+}
+
+impl<T> Drop for OnceCell<T> {
+    fn drop(&mut self) {
+        if self.once.is_completed() {
+            unsafe {
+                (*self.value.get()).as_mut_ptr().drop_in_place();
+            }
+        }
+    }
+}
+
+// The value can be set from one core and read from another, so it needs to
+// be both Send and Sync
+unsafe impl<T: Send + Sync> Sync for OnceCell<T> { }
+unsafe impl<T: Send> Send for OnceCell<T> { }
+
+/// A value which is initialised the first time that it is accessed, which
+/// makes it suitable for statics that can't be constructed in a const context
+pub struct Lazy<T, F = fn() -> T> {
+    cell: OnceCell<T>,
+    init: UnsafeCell<Option<F>>,
+}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    loom_const_fn! {
+        /// Construct a new lazy value, which will be initialised by `f`
+        pub fn new(f: F) -> Lazy<T, F> {
+            Lazy {
+                cell: OnceCell::new(),
+                init: UnsafeCell::new(Some(f)),
+            }
+        }
+    }
+
+    /// Force the value to be initialised, and return a reference to it
+    pub fn force(this: &Lazy<T, F>) -> &T {
+        this.cell.get_or_init(|| {
+            // The Once guarantees that only one core gets here
+            let f = unsafe { (*this.init.get()).take() };
+            match f {
+                Some(f) => f(),
+                None => panic!("Lazy initialiser has already been taken"),
+            }
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
+
+unsafe impl<T: Send + Sync, F: Send> Sync for Lazy<T, F> { }
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use super::*;
+    use core::sync::atomic::AtomicU32;
+
+    #[test]
+    pub fn call_once_only_runs_once() {
+        let once = Once::new();
+        let count = AtomicU32::new(0);
+        once.call_once(|| { count.fetch_add(1, Ordering::Relaxed); });
+        once.call_once(|| { count.fetch_add(1, Ordering::Relaxed); });
+        assert!(once.is_completed());
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    pub fn once_cell_can_only_be_set_once() {
+        let cell = OnceCell::new();
+        assert_eq!(cell.get(), None);
+        assert_eq!(cell.set(1), Ok(()));
+        assert_eq!(cell.set(2), Err(2));
+        assert_eq!(cell.get_or_init(|| 3), &1);
+    }
+
+    #[test]
+    pub fn lazy_is_initialised_on_first_access() {
+        static VALUE: Lazy<u32> = Lazy::new(|| 42);
+        assert_eq!(*VALUE, 42);
+        assert_eq!(*VALUE, 42);
+    }
+}
@@ -0,0 +1,42 @@
+//! The atomic types and spin hint used by the sync primitives.
+//!
+//! When built with `RUSTFLAGS="--cfg loom"`, these are swapped for loom's
+//! instrumented versions, so that the locks can be model checked on the host.
+//! Loom can't construct its atomics in a const context, so constructors are
+//! declared with `loom_const_fn!` to only be const in normal builds.
+
+#[cfg(not(loom))]
+pub(crate) use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, AtomicU16, AtomicU32};
+#[cfg(not(any(loom, test)))]
+pub(crate) use core::hint::spin_loop;
+
+/// Give up the rest of the time slice when testing on the host, because the
+/// test threads may be sharing a CPU, and spinning would stop the lock holder
+/// from running
+#[cfg(all(test, not(loom)))]
+pub(crate) fn spin_loop() {
+    std::thread::yield_now();
+}
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, AtomicU16, AtomicU32};
+
+/// Let the model checker switch to another thread, since this one can't make
+/// progress until something else has run
+#[cfg(loom)]
+pub(crate) fn spin_loop() {
+    loom::thread::yield_now();
+}
+
+/// Declare a function which is `const`, except when model checking with loom
+macro_rules! loom_const_fn {
+    ($(#[$attr:meta])* $vis:vis fn $($rest:tt)*) => {
+        #[cfg(not(loom))]
+        $(#[$attr])*
+        $vis const fn $($rest)*
+
+        #[cfg(loom)]
+        $(#[$attr])*
+        $vis fn $($rest)*
+    };
+}
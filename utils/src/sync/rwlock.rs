@@ -0,0 +1,262 @@
+use super::primitives::{AtomicU32, spin_loop};
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::Ordering;
+
+/// Set while a writer holds the lock
+const WRITER: u32 = 1 << 31;
+/// Set while a writer is waiting, to stop new readers from starving it
+const WRITER_WAITING: u32 = 1 << 30;
+/// The remaining bits count the number of readers holding the lock
+const READERS: u32 = WRITER_WAITING - 1;
+
+/// A reader-writer spinlock, which allows any number of readers or a single
+/// writer to hold the lock at once.
+///
+/// Waiting writers take priority over new readers, so a steady stream of
+/// readers can't keep a writer out forever.
+pub struct RwLock<T> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+impl<T> RwLock<T> {
+    loom_const_fn! {
+        /// Construct a new lock to protect a value
+        pub fn new(v: T) -> RwLock<T> {
+            RwLock {
+                state: AtomicU32::new(0),
+                data: UnsafeCell::new(v),
+            }
+        }
+    }
+
+    /// Lock the value for reading, waiting for any writers to finish first
+    pub fn read(&self) -> ReadLockedRwLock<'_, T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            spin_loop();
+        }
+    }
+
+    /// If no writer holds or is waiting for the lock, lock it for reading;
+    /// otherwise return None
+    pub fn try_read(&self) -> Option<ReadLockedRwLock<'_, T>> {
+        let state = self.state.load(Ordering::Relaxed);
+        if state & (WRITER | WRITER_WAITING) != 0 || state & READERS == READERS {
+            return None;
+        }
+        if self.compare_and_set(state, state + 1) {
+            Some(ReadLockedRwLock {
+                lock: self
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Lock the value for writing, waiting for all readers and any other
+    /// writer to release it first
+    pub fn write(&self) -> WriteLockedRwLock<'_, T> {
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+
+            // Let readers know that we're waiting so that they back off
+            let state = self.state.load(Ordering::Relaxed);
+            if state & WRITER_WAITING == 0 {
+                self.compare_and_set(state, state | WRITER_WAITING);
+            }
+            spin_loop();
+        }
+    }
+
+    /// If the lock is free, lock it for writing; otherwise return None
+    pub fn try_write(&self) -> Option<WriteLockedRwLock<'_, T>> {
+        let state = self.state.load(Ordering::Relaxed);
+        if state & (WRITER | READERS) != 0 {
+            return None;
+        }
+        // Taking the lock also clears the waiting flag. If there are other
+        // writers waiting they will set it again.
+        if self.compare_and_set(state, WRITER) {
+            Some(WriteLockedRwLock {
+                lock: self
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Get the protected value without taking the lock, so that it can still
+    /// be read if the lock will never be released, such as when panicking.
+    ///
+    /// # Safety
+    ///
+    /// The value may be modified at the same time by a writer, so it must not
+    /// be relied upon unless every other core has been stopped.
+    pub unsafe fn read_unchecked(&self) -> &T {
+        &*self.data.get()
+    }
+
+    /// Release a read lock. This method must only be called when dropping
+    /// ReadLockedRwLock
+    fn read_unlock(&self) {
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if self.compare_and_set_release(state, state - 1) {
+                return;
+            }
+        }
+    }
+
+    /// Release the write lock. This method must only be called when dropping
+    /// WriteLockedRwLock
+    fn write_unlock(&self) {
+        // Preserve the waiting flag if another writer set it while we held
+        // the lock
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if self.compare_and_set_release(state, state & !WRITER) {
+                return;
+            }
+        }
+    }
+
+    #[cfg(not(feature = "no-atomics"))]
+    fn compare_and_set(&self, current: u32, new: u32) -> bool {
+        self.state.compare_exchange_weak(current, new, Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+
+    #[cfg(not(feature = "no-atomics"))]
+    fn compare_and_set_release(&self, current: u32, new: u32) -> bool {
+        self.state.compare_exchange_weak(current, new, Ordering::Release, Ordering::Relaxed).is_ok()
+    }
+
+    #[cfg(feature = "no-atomics")]
+    fn compare_and_set(&self, current: u32, new: u32) -> bool {
+        if self.state.load(Ordering::Acquire) == current {
+            self.state.store(new, Ordering::Relaxed);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[cfg(feature = "no-atomics")]
+    fn compare_and_set_release(&self, current: u32, new: u32) -> bool {
+        if self.state.load(Ordering::Relaxed) == current {
+            self.state.store(new, Ordering::Release);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+unsafe impl<T: Send + Sync> Sync for RwLock<T> { }
+
+/// The result of locking a reader-writer lock for reading
+pub struct ReadLockedRwLock<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T> ReadLockedRwLock<'a, T> {
+    /// Get a reference to the value protected by this lock
+    pub fn value(&self) -> &T {
+        unsafe {
+            &*self.lock.data.get()
+        }
+    }
+}
+
+impl<'a, T> Drop for ReadLockedRwLock<'a, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+impl<'a, T> Deref for ReadLockedRwLock<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value()
+    }
+}
+
+/// The result of locking a reader-writer lock for writing
+pub struct WriteLockedRwLock<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T> WriteLockedRwLock<'a, T> {
+    /// Get a reference to the value protected by this lock
+    pub fn value(&self) -> &T {
+        unsafe {
+            &*self.lock.data.get()
+        }
+    }
+
+    /// Get a mutable reference to the value protected by this lock
+    pub fn value_mut(&mut self) -> &mut T {
+        unsafe {
+            &mut *self.lock.data.get()
+        }
+    }
+}
+
+impl<'a, T> Drop for WriteLockedRwLock<'a, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
+
+impl<'a, T> Deref for WriteLockedRwLock<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value()
+    }
+}
+
+impl<'a, T> DerefMut for WriteLockedRwLock<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value_mut()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn readers_share_the_lock() {
+        let l = RwLock::new(1);
+        let a = l.read();
+        let b = l.read();
+        assert_eq!(*a + *b, 2);
+        assert!(l.try_write().is_none());
+    }
+
+    #[test]
+    pub fn writer_excludes_readers() {
+        let l = RwLock::new(1);
+        {
+            let mut w = l.write();
+            *w = 2;
+            assert!(l.try_read().is_none());
+            assert!(l.try_write().is_none());
+        }
+        assert_eq!(*l.read(), 2);
+    }
+
+    #[test]
+    pub fn read_unchecked_ignores_the_writer() {
+        let l = RwLock::new(1);
+        let _w = l.write();
+        assert_eq!(unsafe { *l.read_unchecked() }, 1);
+    }
+}
@@ -0,0 +1,192 @@
+use super::send_event;
+#[cfg(any(not(target_arch = "aarch64"), feature = "no-atomics"))]
+use super::primitives::spin_loop;
+use super::primitives::AtomicU16;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::Ordering;
+
+/// A fair spinlock, which grants the lock to waiters in the order that they
+/// arrived.
+///
+/// Each core that wants the lock takes a ticket by incrementing `next`, then
+/// waits until `owner` reaches its ticket number. Unlocking increments
+/// `owner`, handing the lock to the next core in the queue.
+pub struct TicketLock<T> {
+    next: AtomicU16,
+    owner: AtomicU16,
+    data: UnsafeCell<T>,
+}
+
+impl<T> TicketLock<T> {
+    loom_const_fn! {
+        /// Construct a new ticket lock to protect a value
+        pub fn new(v: T) -> TicketLock<T> {
+            TicketLock {
+                next: AtomicU16::new(0),
+                owner: AtomicU16::new(0),
+                data: UnsafeCell::new(v),
+            }
+        }
+    }
+
+    /// Wait for our turn, then lock the mutex and return a wrapper around the
+    /// protected result
+    #[cfg(any(not(target_arch = "aarch64"), feature = "no-atomics"))]
+    pub fn lock(&self) -> LockedTicketLock<'_, T> {
+        let ticket = self.take_ticket();
+        while self.owner.load(Ordering::Acquire) != ticket {
+            spin_loop();
+        }
+        LockedTicketLock {
+            lock: self
+        }
+    }
+
+    #[cfg(all(not(feature = "no-atomics"), target_arch = "aarch64"))]
+    pub fn lock(&self) -> LockedTicketLock<'_, T> {
+        let next: *const AtomicU16 = &self.next as *const AtomicU16;
+        let owner: *const AtomicU16 = &self.owner as *const AtomicU16;
+        unsafe {
+            asm!("1:
+                    // Take a ticket, by atomically incrementing next
+                    LDAXRH  w1, [$0]
+                    ADD     w2, w1, #1
+                    STXRH   w3, w2, [$0]
+                    CBNZ    w3, 1b
+                    // If it's already our turn, we don't need to wait
+                    LDARH   w2, [$1]
+                    CMP     w2, w1
+                    B.EQ    3f
+                    // Otherwise, make sure the first WFE doesn't block, then
+                    // keep waiting until it's our turn. The exclusive load
+                    // means that the unlocking store will wake us up.
+                    SEVL
+                2:
+                    WFE
+                    LDAXRH  w2, [$1]
+                    CMP     w2, w1
+                    B.NE    2b
+                3:
+                " :: "r"(next), "r"(owner) : "w1", "w2", "w3", "cc", "memory" : "volatile");
+        }
+        LockedTicketLock {
+            lock: self
+        }
+    }
+
+    /// If nobody holds or is waiting for the lock, lock it and return a
+    /// wrapper around the protected result; otherwise return None
+    pub fn try_lock(&self) -> Option<LockedTicketLock<'_, T>> {
+        let owner = self.owner.load(Ordering::Acquire);
+        if self.try_take_ticket(owner) {
+            Some(LockedTicketLock {
+                lock: self
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Unlock the mutex, passing it on to the next ticket. This method must
+    /// only be called when dropping LockedTicketLock
+    fn unlock(&self) {
+        // Only the holder of the lock writes to owner, so this doesn't need
+        // to be an atomic increment
+        let owner = self.owner.load(Ordering::Relaxed);
+        self.owner.store(owner.wrapping_add(1), Ordering::Release);
+        send_event();
+    }
+
+    #[cfg(not(feature = "no-atomics"))]
+    fn take_ticket(&self) -> u16 {
+        self.next.fetch_add(1, Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "no-atomics")]
+    fn take_ticket(&self) -> u16 {
+        let ticket = self.next.load(Ordering::Relaxed);
+        self.next.store(ticket.wrapping_add(1), Ordering::Relaxed);
+        ticket
+    }
+
+    #[cfg(not(feature = "no-atomics"))]
+    fn try_take_ticket(&self, ticket: u16) -> bool {
+        self.next.compare_exchange(ticket, ticket.wrapping_add(1), Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+
+    #[cfg(feature = "no-atomics")]
+    fn try_take_ticket(&self, ticket: u16) -> bool {
+        if self.next.load(Ordering::Acquire) == ticket {
+            self.next.store(ticket.wrapping_add(1), Ordering::Relaxed);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+unsafe impl<T: Send> Sync for TicketLock<T> { }
+
+/// The result of locking a ticket lock
+pub struct LockedTicketLock<'a, T> {
+    lock: &'a TicketLock<T>,
+}
+
+impl<'a, T> LockedTicketLock<'a, T> {
+    /// Get a reference to the value protected by this lock
+    pub fn value(&self) -> &T {
+        unsafe {
+            &*self.lock.data.get()
+        }
+    }
+
+    /// Get a mutable reference to the value protected by this lock
+    pub fn value_mut(&mut self) -> &mut T {
+        unsafe {
+            &mut *self.lock.data.get()
+        }
+    }
+}
+
+impl<'a, T> Drop for LockedTicketLock<'a, T> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+impl<'a, T> Deref for LockedTicketLock<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value()
+    }
+}
+
+impl<'a, T> DerefMut for LockedTicketLock<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value_mut()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn repeated_acquire() {
+        let m = TicketLock::new(());
+        drop(m.lock());
+        drop(m.lock());
+        assert!(m.try_lock().is_some());
+    }
+
+    #[test]
+    pub fn try_lock_fails_while_held() {
+        let m = TicketLock::new(());
+        let guard = m.lock();
+        assert!(m.try_lock().is_none());
+        drop(guard);
+        assert!(m.try_lock().is_some());
+    }
+}
@@ -0,0 +1,100 @@
+//! Decoding UTF-8 from a stream of bytes, such as a UART.
+
+/// The character that invalid sequences are replaced with
+pub const REPLACEMENT_CHARACTER: char = '\u{FFFD}';
+
+/// Reads characters from a source of UTF-8 bytes.
+///
+/// Invalid sequences are replaced by U+FFFD, in the same way as
+/// `String::from_utf8_lossy`: each maximal prefix of a valid sequence is
+/// replaced by a single replacement character. The byte that ended an
+/// invalid sequence is kept and decoded as the start of the next character,
+/// so a corrupt byte can't swallow the character after it.
+pub struct Utf8Reader<R> {
+    source: R,
+    /// A byte that was read but not used by the last character
+    pending: Option<u8>,
+}
+
+impl<R: FnMut() -> u8> Utf8Reader<R> {
+    /// Construct a reader that gets each byte by calling `source`
+    pub fn new(source: R) -> Utf8Reader<R> {
+        Utf8Reader {
+            source,
+            pending: None,
+        }
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        match self.pending.take() {
+            Some(byte) => byte,
+            None => (self.source)(),
+        }
+    }
+
+    /// Read the next character, blocking until the whole of it is available
+    pub fn read_char(&mut self) -> char {
+        let first = self.next_byte();
+        // The initial bits of the code point, the number of continuation
+        // bytes, and the range that the first continuation byte must be in
+        // to rule out overlong encodings, surrogates and values above U+10FFFF
+        let (mut code, continuation_bytes, mut low, mut high) = match first {
+            0x00..=0x7F => return first as char,
+            0xC2..=0xDF => (u32::from(first & 0x1F), 1, 0x80, 0xBF),
+            0xE0 => (u32::from(first & 0x0F), 2, 0xA0, 0xBF),
+            0xE1..=0xEC | 0xEE..=0xEF => (u32::from(first & 0x0F), 2, 0x80, 0xBF),
+            0xED => (u32::from(first & 0x0F), 2, 0x80, 0x9F),
+            0xF0 => (u32::from(first & 0x07), 3, 0x90, 0xBF),
+            0xF1..=0xF3 => (u32::from(first & 0x07), 3, 0x80, 0xBF),
+            0xF4 => (u32::from(first & 0x07), 3, 0x80, 0x8F),
+            _ => return REPLACEMENT_CHARACTER,
+        };
+
+        for _ in 0..continuation_bytes {
+            let byte = self.next_byte();
+            if byte < low || byte > high {
+                self.pending = Some(byte);
+                return REPLACEMENT_CHARACTER;
+            }
+            code = (code << 6) | u32::from(byte & 0x3F);
+            low = 0x80;
+            high = 0xBF;
+        }
+
+        core::char::from_u32(code).unwrap_or(REPLACEMENT_CHARACTER)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::string::String;
+
+    /// Decode all of `bytes`, and check that it matches the standard library
+    fn check(bytes: &[u8]) {
+        let mut iter = bytes.iter().copied();
+        let mut reader = Utf8Reader::new(|| iter.next().unwrap());
+        let expected = String::from_utf8_lossy(bytes);
+        for c in expected.chars() {
+            assert_eq!(reader.read_char(), c, "decoding {:x?}", bytes);
+        }
+    }
+
+    #[test]
+    pub fn valid_sequences() {
+        check("hello".as_bytes());
+        check("£€𝄞\u{10FFFF}\u{80}\u{7FF}\u{800}\u{FFFF}\u{10000}".as_bytes());
+    }
+
+    #[test]
+    pub fn invalid_sequences() {
+        // Bytes that can't start a sequence
+        check(&[0x80, b'a', 0xBF, 0xC0, 0xC1, 0xF5, 0xFF, b'b']);
+        // Truncated sequences followed by ASCII and by another sequence
+        check(&[0xE2, 0x82, b'a', 0xF0, 0x9D, 0x84, 0xC2, 0xA3]);
+        // Overlong encodings
+        check(&[0xC0, 0x80, 0xE0, 0x80, 0x80, 0xF0, 0x80, 0x80, 0x80, b'c']);
+        // Surrogates and values above U+10FFFF
+        check(&[0xED, 0xA0, 0x80, 0xF4, 0x90, 0x80, 0x80, b'd']);
+    }
+}